@@ -0,0 +1,67 @@
+use quiz_game_rust::{
+    packs::{find_pack_files, read_pack},
+    validation::validate_pack,
+};
+use std::{env, path::Path, path::PathBuf, process::ExitCode};
+
+// Usage: quiz-pack-check <pack file or directory>...
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprintln!("Usage: quiz-pack-check <pack file or directory>...");
+        return ExitCode::from(2);
+    }
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for arg in args {
+        let path = Path::new(&arg);
+        if path.is_dir() {
+            match find_pack_files(path) {
+                Ok(mut files) => paths.append(&mut files),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return ExitCode::from(2);
+                }
+            }
+        } else {
+            paths.push(path.to_path_buf());
+        }
+    }
+
+    let mut failed_packs = 0;
+    for path in &paths {
+        let pack = match read_pack(path) {
+            Ok(pack) => pack,
+            Err(error) => {
+                println!("FAIL {}", error);
+                failed_packs += 1;
+                continue;
+            }
+        };
+
+        let problems = validate_pack(&pack);
+        if problems.is_empty() {
+            println!(
+                "OK   {} ({} questions)",
+                path.display(),
+                pack.questions.len()
+            );
+        } else {
+            println!("FAIL {}", path.display());
+            for problem in problems {
+                println!("     {}", problem);
+            }
+            failed_packs += 1;
+        }
+    }
+
+    println!(
+        "{} of {} packs valid",
+        paths.len() - failed_packs,
+        paths.len()
+    );
+    if failed_packs > 0 {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,73 @@
+use quiz_game_rust::import::import_pack;
+use std::{env, fs, path::PathBuf, process::ExitCode};
+
+const USAGE: &str =
+    "Usage: quiz-pack-import <file.csv|file.tsv> [--name <pack name>] [--out <pack.json>]";
+
+// Converts a spreadsheet of questions into a pack file
+fn main() -> ExitCode {
+    let mut input = None;
+    let mut name = None;
+    let mut out = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => name = args.next(),
+            "--out" => out = args.next(),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let input = match input {
+        Some(input) => input,
+        None => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let data = match fs::read_to_string(&input) {
+        Ok(data) => data,
+        Err(error) => {
+            eprintln!("{}: {}", input.display(), error);
+            return ExitCode::FAILURE;
+        }
+    };
+    let delimiter = match input.extension().and_then(|ext| ext.to_str()) {
+        Some("tsv") | Some("tab") => '\t',
+        _ => ',',
+    };
+    let name = name.unwrap_or_else(|| {
+        input
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("imported pack")
+            .to_string()
+    });
+
+    let pack = match import_pack(&data, delimiter, &name) {
+        Ok(pack) => pack,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}: {}", input.display(), error);
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let json = serde_json::to_string_pretty(&pack).unwrap();
+    match out {
+        Some(out) => match fs::write(&out, json) {
+            Ok(_) => println!("Wrote {} questions to {}", pack.questions.len(), out),
+            Err(error) => {
+                eprintln!("{}: {}", out, error);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => println!("{}", json),
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,325 @@
+use jsonwebtoken::Algorithm;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, env, fs, sync::OnceLock};
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub address: String,
+    pub packs_dir: String,
+    pub database_path: String,
+    pub jwt: JwtConfig,
+    // WSS listener, served next to the plain listener unless serve_plain is off
+    pub tls: Option<TlsConfig>,
+    pub heartbeat: HeartbeatConfig,
+    pub rate_limit: RateLimitConfig,
+    pub queue: QueueConfig,
+    pub shutdown: ShutdownConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    // How long running games may continue after a shutdown signal before they are ended
+    pub grace_period_sec: u64,
+    // Sent to clients in serverShutdown, QUIZ_SHUTDOWN_REASON overrides it at signal time
+    pub reason: Option<String>,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            grace_period_sec: 60,
+            reason: None,
+        }
+    }
+}
+
+// Outgoing messages buffered per connection before the overflow policy kicks in
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    // Discard queued timer ticks first, disconnect if there are none
+    DropOldestTicks,
+    Disconnect,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            capacity: 256,
+            overflow: OverflowPolicy::DropOldestTicks,
+        }
+    }
+}
+
+// Per connection token buckets, keyed by command name ("invalid" for unparsable messages)
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub default_budget: CommandBudget,
+    pub commands: HashMap<String, CommandBudget>,
+    // Rejected commands allowed before disconnecting, one is forgiven every violation_decay_sec
+    pub max_violations: u32,
+    pub violation_decay_sec: u64,
+    // Failed joins per IP (unknown rooms or codes, wrong passwords and invites), shared by
+    // all connections from that IP
+    pub failed_join_budget: CommandBudget,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CommandBudget {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let budget = |capacity: f64, refill_per_sec: f64| CommandBudget {
+            capacity,
+            refill_per_sec,
+        };
+        RateLimitConfig {
+            default_budget: budget(20.0, 10.0),
+            commands: HashMap::from([
+                ("createRoom".to_string(), budget(3.0, 0.2)),
+                ("joinRoom".to_string(), budget(5.0, 0.5)),
+                ("broadcastMessage".to_string(), budget(5.0, 1.0)),
+                ("writeAnswer".to_string(), budget(3.0, 1.0)),
+                ("invalid".to_string(), budget(5.0, 1.0)),
+            ]),
+            max_violations: 20,
+            violation_decay_sec: 10,
+            failed_join_budget: budget(5.0, 0.1),
+        }
+    }
+}
+
+// Server pings every interval_sec and drops connections after max_missed_pongs unanswered pings
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    pub interval_sec: u64,
+    pub max_missed_pongs: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval_sec: 15,
+            max_missed_pongs: 2,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    // A zero interval would ping in a busy loop, zero missed pongs drops every connection
+    fn validate(&self) -> Result<(), String> {
+        if self.interval_sec == 0 {
+            return Err("heartbeat.interval_sec must be at least 1".to_string());
+        }
+        if self.max_missed_pongs == 0 {
+            return Err("heartbeat.max_missed_pongs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TlsConfig {
+    pub address: String,
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    #[serde(default)]
+    pub key_path: Option<String>,
+    #[serde(default)]
+    pub pkcs12_path: Option<String>,
+    #[serde(default)]
+    pub pkcs12_password_env: Option<String>,
+    #[serde(default = "default_true")]
+    pub serve_plain: bool,
+    #[serde(default = "default_reload_interval_sec")]
+    pub reload_interval_sec: u64,
+    // Connections that haven't finished the TLS handshake by then are dropped
+    #[serde(default = "default_handshake_timeout_sec")]
+    pub handshake_timeout_sec: u64,
+}
+
+impl TlsConfig {
+    // A zero interval would poll the certificate files in a busy loop, a zero timeout
+    // drops every connection
+    fn validate(&self) -> Result<(), String> {
+        if self.reload_interval_sec == 0 {
+            return Err("tls.reload_interval_sec must be at least 1".to_string());
+        }
+        if self.handshake_timeout_sec == 0 {
+            return Err("tls.handshake_timeout_sec must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_reload_interval_sec() -> u64 {
+    30
+}
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT_SEC: u64 = 10;
+
+fn default_handshake_timeout_sec() -> u64 {
+    DEFAULT_HANDSHAKE_TIMEOUT_SEC
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JwtConfig {
+    // Key used to sign new tokens, the other keys are only used for verification
+    pub signing_kid: String,
+    pub keys: Vec<JwtKeyConfig>,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig {
+            signing_kid: "default".to_string(),
+            keys: vec![JwtKeyConfig {
+                kid: "default".to_string(),
+                algorithm: Algorithm::HS256,
+                secret: None,
+                secret_env: Some("QUIZ_JWT_SECRET".to_string()),
+                secret_file: None,
+                private_key_path: None,
+                public_key_path: None,
+            }],
+        }
+    }
+}
+
+// HS* keys take a secret (inline, env var or file), RS*/EdDSA keys take PEM files
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JwtKeyConfig {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub secret_env: Option<String>,
+    #[serde(default)]
+    pub secret_file: Option<String>,
+    #[serde(default)]
+    pub private_key_path: Option<String>,
+    #[serde(default)]
+    pub public_key_path: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "127.0.0.1:9001".to_string(),
+            packs_dir: "packs".to_string(),
+            database_path: "quiz.db".to_string(),
+            jwt: JwtConfig::default(),
+            tls: None,
+            heartbeat: HeartbeatConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            queue: QueueConfig::default(),
+            shutdown: ShutdownConfig::default(),
+        }
+    }
+}
+
+// Reads the config file named by QUIZ_CONFIG (config.json by default), missing file means defaults
+pub fn init_config() -> Result<(), String> {
+    let path = env::var("QUIZ_CONFIG").unwrap_or_else(|_| "config.json".to_string());
+    let config = match fs::read_to_string(&path) {
+        Ok(data) => match serde_json::from_str::<Config>(&data) {
+            Ok(config) => config,
+            Err(error) => return Err(format!("init_config: {}: {}", path, error)),
+        },
+        Err(_) => {
+            info!("No config file at {}, using defaults", path);
+            Config::default()
+        }
+    };
+    if let Err(error) = config.heartbeat.validate() {
+        return Err(format!("init_config: {}: {}", path, error));
+    }
+    if let Some(Err(error)) = config.tls.as_ref().map(TlsConfig::validate) {
+        return Err(format!("init_config: {}: {}", path, error));
+    }
+
+    match CONFIG.set(config) {
+        Ok(_) => Ok(()),
+        Err(_) => Err("init_config: Config already initialized".to_string()),
+    }
+}
+
+pub fn get_config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(json: &str) -> HeartbeatConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn accepts_the_default_heartbeat() {
+        assert!(HeartbeatConfig::default().validate().is_ok());
+        assert!(heartbeat(r#"{"interval_sec": 1, "max_missed_pongs": 1}"#)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_zero_heartbeat_values() {
+        assert_eq!(
+            heartbeat(r#"{"interval_sec": 0, "max_missed_pongs": 2}"#).validate(),
+            Err("heartbeat.interval_sec must be at least 1".to_string())
+        );
+        assert_eq!(
+            heartbeat(r#"{"interval_sec": 15, "max_missed_pongs": 0}"#).validate(),
+            Err("heartbeat.max_missed_pongs must be at least 1".to_string())
+        );
+    }
+
+    fn tls(json: &str) -> TlsConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn accepts_the_default_tls_intervals() {
+        assert!(tls(r#"{"address": "0.0.0.0:9443"}"#).validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_a_zero_tls_reload_interval() {
+        assert_eq!(
+            tls(r#"{"address": "0.0.0.0:9443", "reload_interval_sec": 0}"#).validate(),
+            Err("tls.reload_interval_sec must be at least 1".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_a_zero_tls_handshake_timeout() {
+        assert_eq!(
+            tls(r#"{"address": "0.0.0.0:9443", "handshake_timeout_sec": 0}"#).validate(),
+            Err("tls.handshake_timeout_sec must be at least 1".to_string())
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use serde_json::{json, Value};
+use std::fmt;
+
+use crate::models::communication::Response;
+
+// Every failure a client can be told about. The code and reason of a variant never
+// change once released, new failures get new variants
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerError {
+    InvalidCommand(String),
+    InvalidToken(String),
+    RateLimited {
+        command: String,
+        retry_after_ms: u64,
+    },
+    SessionExpired,
+    SessionRevoked,
+    SessionMismatch,
+    UserRemoved,
+    UserExists,
+    UserNotFound,
+    UserAlreadyActive,
+    RoomNotFound(String),
+    RoomFull {
+        max_players: i32,
+    },
+    GameInProgress,
+    GameNotStarted,
+    NotHost,
+    PackNotFound(String),
+    ShuttingDown,
+    ConnectionNotFound,
+    ElementNotFound(String),
+    Internal(String),
+    InvalidSettings(String),
+    PackNotSelected,
+    InvalidPassword,
+    InvalidInvite(String),
+}
+
+impl ServerError {
+    // Codes 2 and 3 predate this enum and keep their meaning: every auth failure is 2,
+    // told apart by its reason. The other failures used to share code 0
+    pub fn code(&self) -> i32 {
+        match self {
+            ServerError::InvalidCommand(_) => 1,
+            ServerError::InvalidToken(_)
+            | ServerError::SessionExpired
+            | ServerError::SessionRevoked
+            | ServerError::SessionMismatch
+            | ServerError::UserRemoved => 2,
+            ServerError::RateLimited { .. } => 3,
+            ServerError::UserExists => 4,
+            ServerError::UserNotFound => 5,
+            ServerError::UserAlreadyActive => 6,
+            ServerError::RoomNotFound(_) => 7,
+            ServerError::RoomFull { .. } => 8,
+            ServerError::GameInProgress => 9,
+            ServerError::GameNotStarted => 10,
+            ServerError::NotHost => 11,
+            ServerError::PackNotFound(_) => 12,
+            ServerError::ShuttingDown => 13,
+            ServerError::ConnectionNotFound => 14,
+            ServerError::ElementNotFound(_) => 15,
+            ServerError::Internal(_) => 16,
+            ServerError::InvalidSettings(_) => 17,
+            ServerError::PackNotSelected => 18,
+            ServerError::InvalidPassword => 19,
+            ServerError::InvalidInvite(_) => 20,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ServerError::InvalidCommand(_) => "invalidCommand",
+            ServerError::InvalidToken(_) => "invalidToken",
+            ServerError::RateLimited { .. } => "rateLimited",
+            ServerError::SessionExpired => "sessionExpired",
+            ServerError::SessionRevoked => "sessionRevoked",
+            ServerError::SessionMismatch => "sessionMismatch",
+            ServerError::UserRemoved => "userRemoved",
+            ServerError::UserExists => "userExists",
+            ServerError::UserNotFound => "userNotFound",
+            ServerError::UserAlreadyActive => "userAlreadyActive",
+            ServerError::RoomNotFound(_) => "roomNotFound",
+            ServerError::RoomFull { .. } => "roomFull",
+            ServerError::GameInProgress => "gameInProgress",
+            ServerError::GameNotStarted => "gameNotStarted",
+            ServerError::NotHost => "notHost",
+            ServerError::PackNotFound(_) => "packNotFound",
+            ServerError::ShuttingDown => "shuttingDown",
+            ServerError::ConnectionNotFound => "connectionNotFound",
+            ServerError::ElementNotFound(_) => "elementNotFound",
+            ServerError::Internal(_) => "internal",
+            ServerError::InvalidSettings(_) => "invalidSettings",
+            ServerError::PackNotSelected => "packNotSelected",
+            ServerError::InvalidPassword => "invalidPassword",
+            ServerError::InvalidInvite(_) => "invalidInvite",
+        }
+    }
+
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            ServerError::InvalidCommand(message)
+            | ServerError::InvalidToken(message)
+            | ServerError::InvalidSettings(message)
+            | ServerError::InvalidInvite(message) => Some(json!({ "message": message })),
+            ServerError::RateLimited {
+                command,
+                retry_after_ms,
+            } => Some(json!({ "command": command, "retryAfterMs": retry_after_ms })),
+            ServerError::RoomNotFound(room_id) => Some(json!({ "roomId": room_id })),
+            ServerError::RoomFull { max_players } => Some(json!({ "maxPlayers": max_players })),
+            ServerError::PackNotFound(pack_id) => Some(json!({ "packId": pack_id })),
+            ServerError::ElementNotFound(id) => Some(json!({ "id": id })),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::InvalidCommand(message) => write!(f, "Invalid command: {}", message),
+            ServerError::InvalidToken(message) => write!(f, "Invalid token: {}", message),
+            ServerError::RateLimited { command, .. } => write!(f, "Too many {} commands", command),
+            ServerError::SessionExpired => write!(f, "Session expired"),
+            ServerError::SessionRevoked => write!(f, "Session revoked"),
+            ServerError::SessionMismatch => write!(f, "Session belongs to another user"),
+            ServerError::UserRemoved => write!(f, "User has been removed"),
+            ServerError::UserExists => write!(f, "User already exists"),
+            ServerError::UserNotFound => write!(f, "User does not exist"),
+            ServerError::UserAlreadyActive => write!(f, "User already active"),
+            ServerError::RoomNotFound(_) => write!(f, "Room does not exist"),
+            ServerError::RoomFull { .. } => write!(f, "Room is full"),
+            ServerError::GameInProgress => write!(f, "Game is in progress"),
+            ServerError::GameNotStarted => write!(f, "Game is not started"),
+            ServerError::NotHost => write!(f, "Only the host can do this"),
+            ServerError::PackNotFound(_) => write!(f, "Pack does not exist"),
+            ServerError::ShuttingDown => write!(f, "Server is shutting down"),
+            ServerError::ConnectionNotFound => write!(f, "Cannot find connection channel"),
+            ServerError::ElementNotFound(id) => write!(f, "Element {} does not exist in list", id),
+            ServerError::Internal(message) => write!(f, "Internal error: {}", message),
+            ServerError::InvalidSettings(message) => {
+                write!(f, "Invalid room settings: {}", message)
+            }
+            ServerError::PackNotSelected => {
+                write!(f, "No pack selected and the room has no default pack")
+            }
+            ServerError::InvalidPassword => write!(f, "Wrong room password"),
+            ServerError::InvalidInvite(message) => write!(f, "Invalid invite: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+impl From<ServerError> for Response {
+    fn from(error: ServerError) -> Self {
+        Response::errorResponse {
+            errorText: error.to_string(),
+            errorCode: error.code(),
+            reason: error.reason().to_string(),
+            details: error.details(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn all_errors() -> Vec<ServerError> {
+        vec![
+            ServerError::InvalidCommand(String::new()),
+            ServerError::InvalidToken(String::new()),
+            ServerError::RateLimited {
+                command: "joinRoom".to_string(),
+                retry_after_ms: 100,
+            },
+            ServerError::SessionExpired,
+            ServerError::SessionRevoked,
+            ServerError::SessionMismatch,
+            ServerError::UserRemoved,
+            ServerError::UserExists,
+            ServerError::UserNotFound,
+            ServerError::UserAlreadyActive,
+            ServerError::RoomNotFound(String::new()),
+            ServerError::RoomFull { max_players: 6 },
+            ServerError::GameInProgress,
+            ServerError::GameNotStarted,
+            ServerError::NotHost,
+            ServerError::PackNotFound(String::new()),
+            ServerError::ShuttingDown,
+            ServerError::ConnectionNotFound,
+            ServerError::ElementNotFound(String::new()),
+            ServerError::Internal(String::new()),
+            ServerError::InvalidSettings(String::new()),
+            ServerError::PackNotSelected,
+            ServerError::InvalidPassword,
+            ServerError::InvalidInvite(String::new()),
+        ]
+    }
+
+    #[test]
+    fn auth_failures_keep_code_2() {
+        for error in [
+            ServerError::InvalidToken(String::new()),
+            ServerError::SessionExpired,
+            ServerError::SessionRevoked,
+            ServerError::SessionMismatch,
+            ServerError::UserRemoved,
+        ] {
+            assert_eq!(error.code(), 2, "{:?}", error);
+        }
+        let rate_limited = ServerError::RateLimited {
+            command: "joinRoom".to_string(),
+            retry_after_ms: 100,
+        };
+        assert_eq!(rate_limited.code(), 3);
+    }
+
+    #[test]
+    fn other_failures_have_their_own_code() {
+        let mut codes = HashSet::new();
+        for error in all_errors() {
+            assert!(error.code() > 0, "{:?}", error);
+            if error.code() != 2 {
+                assert!(codes.insert(error.code()), "{:?} reuses a code", error);
+            }
+        }
+    }
+
+    #[test]
+    fn reasons_are_unique() {
+        let mut reasons = HashSet::new();
+        for error in all_errors() {
+            assert!(reasons.insert(error.reason()), "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn converts_to_an_error_response() {
+        match Response::from(ServerError::RoomFull { max_players: 6 }) {
+            Response::errorResponse {
+                errorText,
+                errorCode,
+                reason,
+                details,
+            } => {
+                assert_eq!(errorText, "Room is full");
+                assert_eq!(errorCode, 8);
+                assert_eq!(reason, "roomFull");
+                assert_eq!(details, Some(json!({ "maxPlayers": 6 })));
+            }
+            _ => panic!("expected an error response"),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod command_handler;
 pub mod connection_handler;
 pub mod game_handler;
+pub mod http_handler;
 pub mod timeout_handler;
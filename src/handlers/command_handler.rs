@@ -1,42 +1,58 @@
 use crate::{
+    errors::ServerError,
     handlers::game_handler::handle_game,
-    helpers::{connect_user_to_room, get_list_element, get_room_user_list},
-    jwtoken::{decode_token, generate_token},
+    helpers::{
+        connect_user_to_room, edit_list_element, get_list_element, get_room_user_list,
+        send_game_command,
+    },
+    invites::{
+        check_invite, issue_invite_token, redeem_invite, revoke_invites, Invite,
+        DEFAULT_INVITE_VALID_SEC,
+    },
+    jwtoken::decode_token,
     models::{
-        communication::{AuthorizedCommand, CommandTokenPair, Response, UnauthorizedCommand},
+        communication::{
+            AuthorizedCommand, CommandTokenPair, Response, UnauthorizedCommand,
+            UnauthorizedCommandRequest,
+        },
         game::*,
-        lobby::{Room, User, UserColors},
+        lobby::{LateJoinPolicy, Room, RoomSettings, User, UserColors},
     },
+    packs::get_pack_info,
+    passwords::{hash_password, verify_password},
+    peer_queue::PeerSender,
+    room_codes::{add_room, find_room},
+    room_list::{list_public_rooms, subscribe_room_list, unsubscribe_room_list, RoomListQuery},
     server_messages::*,
+    sessions::{issue_session_token, rotate_session, validate_session, Session},
+    shutdown::is_shutting_down,
+    state::AppState,
 };
-use futures_channel::mpsc::UnboundedSender;
-use log::info;
-use std::fs;
+use log::{info, warn};
 use std::{
     collections::HashMap,
+    net::SocketAddr,
     sync::{Arc, Mutex},
 };
-use tungstenite::protocol::Message;
 use uuid::Uuid;
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<HashMap<String, Tx>>>;
-type UserList = Arc<Mutex<Vec<User>>>;
-type RoomList = Arc<Mutex<Vec<Room>>>;
-type GameList = Arc<Mutex<HashMap<String, Tx>>>;
-type Lists = (PeerMap, UserList, RoomList, GameList);
+type PeerMap = Arc<Mutex<HashMap<String, PeerSender>>>;
+type GameStateList = Arc<Mutex<HashMap<String, GameState>>>;
+type PackList = Arc<Mutex<HashMap<String, Pack>>>;
+type SessionList = Arc<Mutex<HashMap<String, Session>>>;
+type InviteList = Arc<Mutex<HashMap<String, Invite>>>;
 type MutexId = Arc<Mutex<String>>;
 
-// pub fn execute_command(command: &CommandTokenPair, lists: Lists, addr: &SocketAddr) {
+// pub fn execute_command(command: &CommandTokenPair, state: AppState, addr: &SocketAddr) {
 //     let token_info = decode_token(&command.token);
 
-//     let users = lists.1.lock().unwrap();
+//     let users = state.users.lock().unwrap();
 //     let current_user = match users.iter().find(|user| user.id == addr.1) {
 //         Some(user) => Some(user.clone()),
 //         None => None,
 //     };
 
-//     let rooms = lists.2.lock().unwrap();
+//     let rooms = state.rooms.lock().unwrap();
 //     let current_room = match current_user {
 //         Some(ref user) => match rooms.iter().find(|room| room.id == user.roomId) {
 //             Some(room) => Some(room.clone()),
@@ -45,7 +61,7 @@ type MutexId = Arc<Mutex<String>>;
 //         None => None,
 //     };
 
-//     let games = lists.3.lock().unwrap();
+//     let games = state.games.lock().unwrap();
 //     let current_game = match current_room {
 //         Some(ref room) => match games.iter().find(|game| game.0 == &room.id) {
 //             Some(game) => Some((game.0.clone(), game.1.clone())),
@@ -69,7 +85,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: "Token valid".to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //                 Err(_) => (),
@@ -82,7 +98,7 @@ type MutexId = Arc<Mutex<String>>;
 //                         let response = Response::errorReponse {
 //                             errorText: err.to_string(),
 //                         };
-//                         send_message(response, &lists.0, &addr.0);
+//                         send_message(response, &state.peers, &addr.0);
 //                         return;
 //                     }
 //                 };
@@ -91,10 +107,10 @@ type MutexId = Arc<Mutex<String>>;
 //                 token: create_room_result.1,
 //             };
 
-//             lists.1.lock().unwrap().push(create_room_result.0);
-//             lists.2.lock().unwrap().push(create_room_result.2);
+//             state.users.lock().unwrap().push(create_room_result.0);
+//             state.rooms.lock().unwrap().push(create_room_result.2);
 
-//             send_message(response, &lists.0, &addr.0);
+//             send_message(response, &state.peers, &addr.0);
 //             info!("Successful room creation for: {}", &addr.0);
 //         }
 //         Command::joinRoom {
@@ -117,7 +133,7 @@ type MutexId = Arc<Mutex<String>>;
 //                             let response = Response::errorReponse {
 //                                 errorText: "User already active".to_string(),
 //                             };
-//                             send_message(response, &lists.0, &addr.0);
+//                             send_message(response, &state.peers, &addr.0);
 //                             return;
 //                         }
 //                         None => (),
@@ -149,7 +165,7 @@ type MutexId = Arc<Mutex<String>>;
 //                         let response = Response::errorReponse {
 //                             errorText: "Max players reached".to_string(),
 //                         };
-//                         send_message(response, &lists.0, &addr.0);
+//                         send_message(response, &state.peers, &addr.0);
 //                         return;
 //                     }
 //                 }
@@ -167,14 +183,14 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: err.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
 
-//             lists.1.lock().unwrap().push(join_room_result.0);
+//             state.users.lock().unwrap().push(join_room_result.0);
 
-//             match edit_list_element(roomId, lists.2.clone(), |room| {
+//             match edit_list_element(roomId, state.rooms.clone(), |room| {
 //                 room.current_players += 1;
 //             }) {
 //                 Ok(_) => (),
@@ -183,19 +199,19 @@ type MutexId = Arc<Mutex<String>>;
 
 //             let response = Response::joinRoomResponse {
 //                 token: join_room_result.1,
-//                 userList: get_room_user_list(&roomId, lists.1.lock().unwrap()),
+//                 userList: get_room_user_list(&roomId, state.users.lock().unwrap()),
 //             };
 
-//             send_message(response, &lists.0, &addr.0);
+//             send_message(response, &state.peers, &addr.0);
 
 //             let broadcast_response = Response::updateUserList {
-//                 userList: get_room_user_list(&roomId, lists.1.lock().unwrap()),
+//                 userList: get_room_user_list(&roomId, state.users.lock().unwrap()),
 //             };
 
 //             broadcast_message_room_except(
 //                 broadcast_response,
-//                 &lists.0,
-//                 &get_room_user_list(&roomId, lists.1.lock().unwrap()),
+//                 &state.peers,
+//                 &get_room_user_list(&roomId, state.users.lock().unwrap()),
 //                 &addr.0,
 //             );
 
@@ -213,7 +229,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: error.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
@@ -224,7 +240,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: "User does not exist".to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             }
@@ -234,7 +250,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: "Game already in progress".to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //                 None => (),
@@ -244,15 +260,15 @@ type MutexId = Arc<Mutex<String>>;
 //                 let response = Response::errorReponse {
 //                     errorText: "Only host can start the game".to_string(),
 //                 };
-//                 send_message(response, &lists.0, &addr.0);
+//                 send_message(response, &state.peers, &addr.0);
 //                 return;
 //             }
 
 //             let broadcast_response = Response::startGame {};
 //             broadcast_message_room_all(
 //                 broadcast_response,
-//                 &lists.0,
-//                 &get_room_user_list(&token_info.roomId.clone(), lists.1.lock().unwrap()),
+//                 &state.peers,
+//                 &get_room_user_list(&token_info.roomId.clone(), state.users.lock().unwrap()),
 //             );
 
 //             // previously: fs::read_to_string("./packs/test.json")
@@ -262,7 +278,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: error.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
@@ -272,19 +288,19 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: error.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
 
 //             tokio::spawn(handle_game(
 //                 (
-//                     lists.0.clone(),
-//                     lists.1.clone(),
-//                     lists.2.clone(),
-//                     lists.3.clone(),
+//                     state.peers.clone(),
+//                     state.users.clone(),
+//                     state.rooms.clone(),
+//                     state.games.clone(),
 //                 ),
-//                 get_room_user_list(&token_info.roomId.clone(), lists.1.lock().unwrap()),
+//                 get_room_user_list(&token_info.roomId.clone(), state.users.lock().unwrap()),
 //                 connection_info.2.unwrap().id.clone(),
 //                 pack,
 //             ));
@@ -300,18 +316,18 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: error.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
 
-//             let user_list = get_room_user_list(&token_info.roomId, lists.1.lock().unwrap());
+//             let user_list = get_room_user_list(&token_info.roomId, state.users.lock().unwrap());
 
 //             let response = Response::updateUserList {
 //                 userList: user_list,
 //             };
 
-//             send_message(response, &lists.0, &addr.0);
+//             send_message(response, &state.peers, &addr.0);
 //             info!("Successful get user list from: {}", &addr.0);
 //         }
 //         Command::broadcastMessage { text } => {
@@ -323,7 +339,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: error.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
@@ -333,9 +349,9 @@ type MutexId = Arc<Mutex<String>>;
 //                 author: token_info.id,
 //             };
 
-//             let user_list = get_room_user_list(&token_info.roomId, lists.1.lock().unwrap());
+//             let user_list = get_room_user_list(&token_info.roomId, state.users.lock().unwrap());
 
-//             broadcast_message_room_all(response, &lists.0, &user_list);
+//             broadcast_message_room_all(response, &state.peers, &user_list);
 //             info!("Successful broadcast to room from: {}", &addr.0);
 //         }
 //         Command::writeAnswer { answer } => {
@@ -348,7 +364,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: error.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
@@ -360,7 +376,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: "User does not exist".to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             }
@@ -372,7 +388,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: "Room does not exist".to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             }
@@ -384,7 +400,7 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: "Game does not exist".to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             }
@@ -410,12 +426,12 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: error.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
 
-//             edit_list_element(&token_info.id, lists.1.clone(), |user| {
+//             edit_list_element(&token_info.id, state.users.clone(), |user| {
 //                 user.name = newName.clone();
 //             })
 //             .unwrap();
@@ -428,12 +444,12 @@ type MutexId = Arc<Mutex<String>>;
 //                     let response = Response::errorReponse {
 //                         errorText: error.to_string(),
 //                     };
-//                     send_message(response, &lists.0, &addr.0);
+//                     send_message(response, &state.peers, &addr.0);
 //                     return;
 //                 }
 //             };
 
-//             edit_list_element(&token_info.id, lists.1.clone(), |user| {
+//             edit_list_element(&token_info.id, state.users.clone(), |user| {
 //                 user.avatarPath = newAvatarPath.clone();
 //             })
 //             .unwrap();
@@ -442,47 +458,72 @@ type MutexId = Arc<Mutex<String>>;
 // }
 
 pub fn execute_unauthorized_command(
-    command: UnauthorizedCommand,
-    lists: Lists,
+    request: UnauthorizedCommandRequest,
+    state: AppState,
     connection_id: MutexId,
+    addr: &SocketAddr,
 ) {
-    match command {
-        UnauthorizedCommand::createRoom { name, avatarPath } => {
+    let request_id = request.requestId;
+    match request.command {
+        UnauthorizedCommand::createRoom {
+            name,
+            avatarPath,
+            settings,
+        } => {
             // Return error if user exists
-            match get_list_element(&connection_id.lock().unwrap().clone(), lists.1.clone()) {
-                Some(_) => {
-                    let response = Response::errorResponse {
-                        errorText: "User already exists".to_string(),
-                        errorCode: 0,
-                    };
-                    send_message(
+            if get_list_element(&connection_id.lock().unwrap().clone(), state.users.clone())
+                .is_some()
+            {
+                let response = Response::from(ServerError::UserExists);
+                send_reply(
+                    response,
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return;
+            }
+
+            let settings = match validate_room_settings(settings, 1, state.packs.clone()) {
+                Ok(settings) => settings,
+                Err(error) => {
+                    let response = Response::from(error);
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
                     return;
                 }
-                None => (),
-            }
+            };
 
             // Try create user, token and room and handle it
-            match create_room(connection_id.clone(), name, avatarPath) {
+            match create_room(
+                connection_id.clone(),
+                name,
+                avatarPath,
+                settings,
+                state.sessions.clone(),
+            ) {
                 Ok(create_room) => {
-                    let room_id = create_room.2.id.clone();
-
-                    lists.1.lock().unwrap().push(create_room.0);
-                    lists.2.lock().unwrap().push(create_room.2);
+                    state.users.lock().unwrap().push(create_room.0);
+                    let room = add_room(create_room.2, state.rooms.clone());
+                    state.notify_room_list_changed();
 
-                    let user_list = get_room_user_list(&room_id, lists.1.clone());
+                    let user_list = get_room_user_list(&room.id, state.users.clone());
 
                     let response = Response::createRoomResponse {
                         token: create_room.1,
+                        roomCode: room.code,
                         userList: user_list.clone(),
+                        settings: room.settings,
                     };
-                    send_message(
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
 
                     let user_list_response = Response::updateUserList {
@@ -490,22 +531,19 @@ pub fn execute_unauthorized_command(
                     };
                     broadcast_message_room_except(
                         user_list_response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &user_list,
                         &connection_id.lock().unwrap().clone(),
                     );
                 }
                 Err(error) => {
-                    let response = Response::errorResponse {
-                        errorText: error,
-                        errorCode: 0,
-                    };
-                    send_message(
+                    let response = Response::from(error);
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
-                    return;
                 }
             }
         }
@@ -513,151 +551,291 @@ pub fn execute_unauthorized_command(
             name,
             avatarPath,
             roomId,
+            password,
+            inviteToken,
         } => {
             // Return error if user exists
-            match get_list_element(&connection_id.lock().unwrap().clone(), lists.1.clone()) {
-                Some(_) => {
-                    let response = Response::errorResponse {
-                        errorText: "User already exists".to_string(),
-                        errorCode: 0,
-                    };
-                    send_message(
-                        response,
-                        lists.0.clone(),
-                        &connection_id.lock().unwrap().clone(),
-                    );
-                    return;
-                }
-                None => (),
+            if get_list_element(&connection_id.lock().unwrap().clone(), state.users.clone())
+                .is_some()
+            {
+                let response = Response::from(ServerError::UserExists);
+                send_reply(
+                    response,
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return;
             }
 
-            // Return if room does not exist
-            let room = match get_list_element(&roomId, lists.2.clone()) {
+            // An IP that keeps failing has to wait before trying again, so room codes
+            // and passwords can't be guessed
+            let allowed = state.failed_joins.lock().unwrap().check(&addr.ip());
+            if let Err(retry_after) = allowed {
+                let response = Response::from(ServerError::RateLimited {
+                    command: "joinRoom".to_string(),
+                    retry_after_ms: retry_after.as_millis().min(u64::MAX as u128) as u64,
+                });
+                send_reply(
+                    response,
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return;
+            }
+
+            // Return if room does not exist, players may give the join code instead of the id
+            let room = match find_room(&roomId, state.rooms.clone()) {
                 Some(room) => room,
                 None => {
-                    let response = Response::errorResponse {
-                        errorText: "Room does not exist".to_string(),
-                        errorCode: 0,
-                    };
-                    send_message(
+                    state
+                        .failed_joins
+                        .lock()
+                        .unwrap()
+                        .record_failure(&addr.ip());
+                    let response = Response::from(ServerError::RoomNotFound(roomId.clone()));
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
                     return;
                 }
             };
 
+            let room_id = room.id.clone();
+
+            // Checked before the room is found full or playing, so clients without the
+            // password or an invite learn nothing about it. The invite is only used up
+            // once the player is in the room
+            let mut invite = None;
+            if room.settings.hasPassword || room.settings.inviteOnly {
+                match check_room_access(&room, &password, &inviteToken, state.invites.clone()) {
+                    Ok(checked_invite) => invite = checked_invite,
+                    Err(error) => {
+                        warn!("Failed attempt to join room {} from {}", &room_id, addr);
+                        state
+                            .failed_joins
+                            .lock()
+                            .unwrap()
+                            .record_failure(&addr.ip());
+                        let response = Response::from(error);
+                        send_reply(
+                            response,
+                            state.peers.clone(),
+                            &connection_id.lock().unwrap().clone(),
+                            &request_id,
+                        );
+                        return;
+                    }
+                }
+            }
+
             // Return if already max players in room
-            if room.current_players >= room.max_players {
-                let response = Response::errorResponse {
-                    errorText: "Room is full".to_string(),
-                    errorCode: 0,
-                };
-                send_message(
+            if room.current_players >= room.settings.maxPlayers {
+                let response = Response::from(ServerError::RoomFull {
+                    max_players: room.settings.maxPlayers,
+                });
+                send_reply(
                     response,
-                    lists.0.clone(),
+                    state.peers.clone(),
                     &connection_id.lock().unwrap().clone(),
+                    &request_id,
                 );
                 return;
             }
 
-            // Return if game in progress
-            if lists.3.lock().unwrap().contains_key(&roomId) {
-                let response = Response::errorResponse {
-                    errorText: "Game is in progress".to_string(),
-                    errorCode: 0,
-                };
-                send_message(
+            // Return if game in progress and the room doesn't take late joiners
+            let game_running = state.games.lock().unwrap().contains_key(&room_id);
+            if game_running && room.settings.lateJoin == LateJoinPolicy::never {
+                let response = Response::from(ServerError::GameInProgress);
+                send_reply(
                     response,
-                    lists.0.clone(),
+                    state.peers.clone(),
                     &connection_id.lock().unwrap().clone(),
+                    &request_id,
                 );
                 return;
             }
 
             // Try create user and token and handle it
-            match join_room(connection_id.clone(), name, avatarPath, &roomId) {
+            match join_room(
+                connection_id.clone(),
+                name,
+                avatarPath,
+                &room_id,
+                state.sessions.clone(),
+            ) {
                 Ok(join_room) => {
-                    lists.1.lock().unwrap().push(join_room.0);
+                    state.users.lock().unwrap().push(join_room.0);
 
                     let user_list = match connect_user_to_room(
-                        &roomId,
+                        &room_id,
                         &connection_id.lock().unwrap().clone(),
-                        (lists.0.clone(), lists.1.clone(), lists.2.clone()),
+                        &state,
                     ) {
                         Ok(list) => list,
                         Err(error) => {
-                            let response = Response::errorResponse {
-                                errorText: error,
-                                errorCode: 0,
-                            };
-                            send_message(
+                            let response = Response::from(error);
+                            send_reply(
                                 response,
-                                lists.0.clone(),
+                                state.peers.clone(),
                                 &connection_id.lock().unwrap().clone(),
+                                &request_id,
                             );
                             return;
                         }
                     };
 
+                    if let Some(invite) = invite {
+                        redeem_invite(&invite.id, state.invites.clone());
+                    }
+
                     let token_response = Response::joinRoomResponse {
                         token: join_room.1,
+                        roomCode: room.code.clone(),
                         userList: user_list.clone(),
+                        settings: room.settings.clone(),
                     };
-                    send_message(
+                    send_reply(
                         token_response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
 
+                    // Late joiner, show the running game
+                    if game_running {
+                        send_game_state(
+                            &connection_id.lock().unwrap().clone(),
+                            &room_id,
+                            state.game_states.clone(),
+                            state.peers.clone(),
+                            &request_id,
+                        );
+                    }
+
                     let user_list_response = Response::updateUserList {
                         userList: user_list.clone(),
                     };
                     broadcast_message_room_except(
                         user_list_response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &user_list,
                         &connection_id.lock().unwrap().clone(),
                     );
                 }
                 Err(error) => {
-                    let response = Response::errorResponse {
-                        errorText: error,
-                        errorCode: 0,
-                    };
-                    send_message(
+                    let response = Response::from(error);
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
-                    return;
                 }
             }
         }
         UnauthorizedCommand::heartbeat {} => {
             info!("Heartbeat from: {}", &connection_id.lock().unwrap().clone());
+            send_ack(
+                "heartbeat",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
+            );
+        }
+        UnauthorizedCommand::listRooms {
+            filter,
+            page,
+            pageSize,
+            subscribe,
+        } => {
+            let query = RoomListQuery {
+                filter,
+                page,
+                page_size: pageSize,
+            };
+            let response = list_public_rooms(&query, &state);
+            if subscribe {
+                subscribe_room_list(
+                    &connection_id.lock().unwrap().clone(),
+                    query,
+                    &response,
+                    state.room_list_subscriptions.clone(),
+                );
+            }
+            send_reply(
+                response,
+                state.peers.clone(),
+                &connection_id.lock().unwrap().clone(),
+                &request_id,
+            );
+        }
+        UnauthorizedCommand::unsubscribeRooms {} => {
+            unsubscribe_room_list(
+                &connection_id.lock().unwrap().clone(),
+                state.room_list_subscriptions.clone(),
+            );
+            send_ack(
+                "unsubscribeRooms",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
+            );
         }
     }
 }
 
 pub fn execute_authorized_command(
     command_token_pair: CommandTokenPair,
-    lists: Lists,
+    state: AppState,
     connection_id: MutexId,
 ) {
+    let request_id = command_token_pair.requestId.clone();
+
     // Validate token
     let token_info = match decode_token(&command_token_pair.token) {
         Ok(info) => info.claims,
         Err(error) => {
-            let response = Response::errorResponse {
-                errorText: error,
-                errorCode: 2,
-            };
-            send_message(
+            let response = Response::from(ServerError::InvalidToken(error.to_string()));
+            send_reply(
+                response,
+                state.peers.clone(),
+                &connection_id.lock().unwrap().clone(),
+                &request_id,
+            );
+            return;
+        }
+    };
+
+    // Return error if the session was revoked, expired or doesn't match the token
+    let session = match validate_session(&token_info, state.sessions.clone()) {
+        Ok(session) => session,
+        Err(error) => {
+            let response = Response::from(error);
+            send_reply(
+                response,
+                state.peers.clone(),
+                &connection_id.lock().unwrap().clone(),
+                &request_id,
+            );
+            return;
+        }
+    };
+
+    // Authoritative user state, the token only says who the user is
+    let user = match get_list_element(&token_info.id, state.users.clone()) {
+        Some(user) => user,
+        None => {
+            let response = Response::from(ServerError::UserRemoved);
+            send_reply(
                 response,
-                lists.0.clone(),
+                state.peers.clone(),
                 &connection_id.lock().unwrap().clone(),
+                &request_id,
             );
             return;
         }
@@ -666,21 +844,19 @@ pub fn execute_authorized_command(
     match command_token_pair.command {
         AuthorizedCommand::reconnectRoom {} => {
             println!("Started RECONNECT 2.1");
-            // let mut peer_map_lock = lists.0.lock().unwrap();
+            // let mut peer_map_lock = state.peers.lock().unwrap();
 
             println!("Locked list of connections 2.1.5");
             // Return if connection is active
-            let peer_map_active = lists.0.lock().unwrap().contains_key(&token_info.id).clone();
+            let peer_map_active = state.peers.lock().unwrap().contains_key(&user.id);
             println!("Got bool for connection active 2.1.5");
             if peer_map_active {
-                let response = Response::errorResponse {
-                    errorText: "User already active".to_string(),
-                    errorCode: 0,
-                };
-                send_message(
+                let response = Response::from(ServerError::UserAlreadyActive);
+                send_reply(
                     response,
-                    lists.0.clone(),
+                    state.peers.clone(),
                     &connection_id.lock().unwrap().clone(),
+                    &request_id,
                 );
                 println!("Returned RECONNECT user active 2.3");
                 return;
@@ -688,22 +864,20 @@ pub fn execute_authorized_command(
             println!("Done user active check 2.1.5");
 
             // Return if this connection has no tx channel
-            let connection_channel = match lists
-                .0
+            let connection_channel = match state
+                .peers
                 .lock()
                 .unwrap()
                 .get(&connection_id.lock().unwrap().clone())
             {
                 Some(tx) => tx.clone(),
                 None => {
-                    let response = Response::errorResponse {
-                        errorText: "Cannot find connection channel".to_string(),
-                        errorCode: 0,
-                    };
-                    send_message(
+                    let response = Response::from(ServerError::ConnectionNotFound);
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
                     println!("Returned RECONNECT connction channel no longer exists 2.3");
                     return;
@@ -711,175 +885,232 @@ pub fn execute_authorized_command(
             };
             println!("Done connection remove check 2.1.5");
 
-            // Check if user has been removed in the meantime
-            let existing_user = get_list_element(&token_info.id, lists.1.clone());
-            match existing_user {
-                Some(_) => (),
-                None => {
-                    let response = Response::errorResponse {
-                        errorText: "User has been removed".to_string(),
-                        errorCode: 2,
-                    };
-                    send_message(
-                        response,
-                        lists.0.clone(),
-                        &connection_id.lock().unwrap().clone(),
-                    );
-                    println!("Returned RECONNECT user no longer exists 2.3");
-                    return;
-                }
-            }
-            println!("Done user remove check 2.1.5");
-
             // Remove and re-insert tx channel with id from token
-            lists
-                .0
+            state
+                .peers
                 .lock()
                 .unwrap()
                 .remove(&connection_id.lock().unwrap().clone());
-            lists
-                .0
+            state
+                .peers
                 .lock()
                 .unwrap()
-                .insert(token_info.id.clone(), connection_channel);
+                .insert(user.id.clone(), connection_channel);
 
             // Change connection ID for connection handler
-            *connection_id.lock().unwrap() = token_info.id.clone();
+            *connection_id.lock().unwrap() = user.id.clone();
 
             // drop(peer_map_lock);
 
             println!("Insert-reinsert 2.1.5");
             // Respond with room user list
             let user_list_response = Response::updateUserList {
-                userList: get_room_user_list(&token_info.roomId, lists.1.clone()).clone(),
+                userList: get_room_user_list(&user.roomId, state.users.clone()).clone(),
             };
-            send_message(user_list_response, lists.0.clone(), &token_info.id);
+            send_reply(
+                user_list_response,
+                state.peers.clone(),
+                &user.id,
+                &request_id,
+            );
+
+            // Resuming is a new session, the old token stops working shortly after
+            match rotate_session(&session.id, &user.id, state.sessions.clone()) {
+                Ok(token) => send_reply(
+                    Response::tokenResponse { token },
+                    state.peers.clone(),
+                    &user.id,
+                    &request_id,
+                ),
+                Err(error) => warn!("Could not rotate session for {}: {}", &user.id, error),
+            }
+
+            // Catch the player up if a game is running in the room
+            send_game_state(
+                &user.id,
+                &user.roomId,
+                state.game_states.clone(),
+                state.peers.clone(),
+                &request_id,
+            );
             println!("Finished RECONNECT 2.2");
         }
-        AuthorizedCommand::startGame { packPath } => {
+        AuthorizedCommand::startGame { packId } => {
             info!(
                 "Start game command from: {}",
                 &connection_id.lock().unwrap().clone()
             );
 
             // Return error if user doesn't exist
-            match get_list_element(&connection_id.lock().unwrap().clone(), lists.1.clone()) {
+            match get_list_element(&connection_id.lock().unwrap().clone(), state.users.clone()) {
                 Some(_) => (),
                 None => {
-                    let response = Response::errorResponse {
-                        errorText: "User does not exist".to_string(),
-                        errorCode: 0,
-                    };
-                    send_message(
+                    let response = Response::from(ServerError::UserNotFound);
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
                     return;
                 }
             }
 
+            // No new games once the server is draining for shutdown
+            if is_shutting_down() {
+                let response = Response::from(ServerError::ShuttingDown);
+                send_reply(
+                    response,
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return;
+            }
+
             // Return error if game exists (i.e. is in progress)
-            if lists.3.lock().unwrap().contains_key(&token_info.roomId) {
-                let response = Response::errorResponse {
-                    errorText: "Game in progress".to_string(),
-                    errorCode: 0,
-                };
-                send_message(
+            if state.games.lock().unwrap().contains_key(&user.roomId) {
+                let response = Response::from(ServerError::GameInProgress);
+                send_reply(
                     response,
-                    lists.0.clone(),
+                    state.peers.clone(),
                     &connection_id.lock().unwrap().clone(),
+                    &request_id,
                 );
                 return;
             }
 
             // Return error if user is not host
-            let is_user_host = get_list_element(&token_info.id, lists.1.clone())
-                .unwrap()
-                .isHost;
-            if !is_user_host {
-                let response = Response::errorResponse {
-                    errorText: "Only host can start game".to_string(),
-                    errorCode: 0,
-                };
-                send_message(
+            if !user.isHost {
+                let response = Response::from(ServerError::NotHost);
+                send_reply(
                     response,
-                    lists.0.clone(),
+                    state.peers.clone(),
                     &connection_id.lock().unwrap().clone(),
+                    &request_id,
                 );
                 return;
             }
-            drop(is_user_host);
 
-            // Broadcast to the room that the game has started
-            let broadcast_response = Response::startGame {};
-            broadcast_message_room_all(
-                broadcast_response,
-                lists.0.clone(),
-                &get_room_user_list(&token_info.roomId.clone(), lists.1.clone()),
-            );
+            let room = match get_list_element(&user.roomId, state.rooms.clone()) {
+                Some(room) => room,
+                None => {
+                    let response = Response::from(ServerError::RoomNotFound(user.roomId.clone()));
+                    send_reply(
+                        response,
+                        state.peers.clone(),
+                        &connection_id.lock().unwrap().clone(),
+                        &request_id,
+                    );
+                    return;
+                }
+            };
 
-            // Read pack data from file to string, return error if did not work
-            // previously: fs::read_to_string("./packs/test.json")
-            let data = match fs::read_to_string(packPath) {
-                Ok(data) => data,
-                Err(error) => {
-                    let response = Response::errorResponse {
-                        errorText: error.to_string(),
-                        errorCode: 0,
-                    };
-                    send_message(
+            // Fall back to the room's default pack
+            let pack_id = match packId.or(room.settings.defaultPackId.clone()) {
+                Some(pack_id) => pack_id,
+                None => {
+                    let response = Response::from(ServerError::PackNotSelected);
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
                     return;
                 }
             };
 
-            // Transform pack data to Pack object, return error if did not work
-            let pack: Pack = match serde_json::from_str(&data) {
-                Ok(pack) => pack,
-                Err(error) => {
-                    let response = Response::errorResponse {
-                        errorText: error.to_string(),
-                        errorCode: 0,
-                    };
-                    send_message(
+            // Look up the pack in the registry, return error if there is no such pack
+            let pack = match state.packs.lock().unwrap().get(&pack_id) {
+                Some(pack) => apply_room_settings(pack.clone(), &room.settings),
+                None => {
+                    let response = Response::from(ServerError::PackNotFound(pack_id.clone()));
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
                     return;
                 }
             };
 
+            // Shown in the public room list
+            match edit_list_element(&user.roomId, state.rooms.clone(), |room| {
+                room.pack_id = Some(pack_id.clone());
+            }) {
+                Ok(_) => state.notify_room_list_changed(),
+                Err(error) => warn!("Could not set pack of room {}: {}", &user.roomId, error),
+            }
+
+            // Broadcast to the room that the game has started
+            let broadcast_response = Response::startGame {};
+            broadcast_message_room_all(
+                broadcast_response,
+                state.peers.clone(),
+                &get_room_user_list(&user.roomId.clone(), state.users.clone()),
+            );
+
             // Spawn a thread to handle game
             tokio::spawn(handle_game(
-                (
-                    lists.0.clone(),
-                    lists.1.clone(),
-                    lists.2.clone(),
-                    lists.3.clone(),
-                ),
-                get_room_user_list(&token_info.roomId.clone(), lists.1.clone()),
-                token_info.roomId.clone(),
+                state.clone(),
+                get_room_user_list(&user.roomId.clone(), state.users.clone()),
+                user.roomId.clone(),
+                pack_id,
                 pack,
             ));
 
+            send_ack(
+                "startGame",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
+            );
             info!("Loading pack success");
         }
-        AuthorizedCommand::getUserList {} => (),
+        AuthorizedCommand::listPacks {} => {
+            let mut packs: Vec<PackInfo> = state
+                .packs
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, pack)| get_pack_info(id, pack))
+                .collect();
+            packs.sort_by(|a, b| a.id.cmp(&b.id));
+
+            let response = Response::packListResponse { packs };
+            send_reply(
+                response,
+                state.peers.clone(),
+                &connection_id.lock().unwrap().clone(),
+                &request_id,
+            );
+        }
+        AuthorizedCommand::getUserList {} => {
+            send_ack(
+                "getUserList",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
+            );
+        }
         AuthorizedCommand::broadcastMessage { text } => {
             // Broadcast to everybody in the room
             let response = Response::newMessage {
-                author: token_info.id,
-                text: text,
+                author: user.id,
+                text,
             };
             broadcast_message_room_all(
                 response,
-                lists.0.clone(),
-                &get_room_user_list(&token_info.roomId, lists.1.clone()),
+                state.peers.clone(),
+                &get_room_user_list(&user.roomId, state.users.clone()),
+            );
+            send_ack(
+                "broadcastMessage",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
             );
         }
         AuthorizedCommand::writeAnswer { answer } => {
@@ -889,48 +1120,43 @@ pub fn execute_authorized_command(
             );
 
             // Return error if user doesn't exist
-            match get_list_element(&connection_id.lock().unwrap().clone(), lists.1.clone()) {
+            match get_list_element(&connection_id.lock().unwrap().clone(), state.users.clone()) {
                 Some(_) => (),
                 None => {
-                    let response = Response::errorResponse {
-                        errorText: "User does not exist".to_string(),
-                        errorCode: 0,
-                    };
-                    send_message(
+                    let response = Response::from(ServerError::UserNotFound);
+                    send_reply(
                         response,
-                        lists.0.clone(),
+                        state.peers.clone(),
                         &connection_id.lock().unwrap().clone(),
+                        &request_id,
                     );
                     return;
                 }
             }
 
             // Return error if game doesn't exist
-            if !lists.3.lock().unwrap().contains_key(&token_info.roomId) {
-                let response = Response::errorResponse {
-                    errorText: "Game is not started".to_string(),
-                    errorCode: 0,
-                };
-                send_message(
+            if !state.games.lock().unwrap().contains_key(&user.roomId) {
+                let response = Response::from(ServerError::GameNotStarted);
+                send_reply(
                     response,
-                    lists.0.clone(),
+                    state.peers.clone(),
                     &connection_id.lock().unwrap().clone(),
+                    &request_id,
                 );
                 return;
             }
 
-            let answer = GameCommand {
-                user_id: token_info.id.to_string(),
-                answer: answer,
+            let answer = GameCommand::writeAnswer {
+                user_id: user.id.to_string(),
+                answer,
             };
-            lists
-                .3
-                .lock()
-                .unwrap()
-                .get(&token_info.roomId)
-                .unwrap()
-                .unbounded_send(Message::Text(serde_json::to_string(&answer).unwrap()))
-                .unwrap();
+            send_game_command(&answer, &user.roomId, state.games.clone());
+            send_ack(
+                "writeAnswer",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
+            );
 
             info!(
                 "Successful answer message from: {}",
@@ -939,23 +1165,438 @@ pub fn execute_authorized_command(
         }
         AuthorizedCommand::changeUsername { newName } => {
             info!("{}", newName);
+            send_ack(
+                "changeUsername",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
+            );
         }
         AuthorizedCommand::changeAvatar { newAvatarPath } => {
             info!("{}", newAvatarPath);
+            send_ack(
+                "changeAvatar",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
+            );
+        }
+        AuthorizedCommand::pauseGame {} => {
+            execute_host_game_command(
+                GameCommand::pauseGame {},
+                &user,
+                state,
+                connection_id,
+                &request_id,
+            );
+        }
+        AuthorizedCommand::resumeGame {} => {
+            execute_host_game_command(
+                GameCommand::resumeGame {},
+                &user,
+                state,
+                connection_id,
+                &request_id,
+            );
+        }
+        AuthorizedCommand::skipQuestion {} => {
+            execute_host_game_command(
+                GameCommand::skipQuestion {},
+                &user,
+                state,
+                connection_id,
+                &request_id,
+            );
+        }
+        AuthorizedCommand::endGame {} => {
+            execute_host_game_command(
+                GameCommand::endGame {},
+                &user,
+                state,
+                connection_id,
+                &request_id,
+            );
+        }
+        AuthorizedCommand::updateRoomSettings { settings } => {
+            info!(
+                "Update room settings command from: {}",
+                &connection_id.lock().unwrap().clone()
+            );
+
+            // Return error if user is not host
+            if !user.isHost {
+                let response = Response::from(ServerError::NotHost);
+                send_reply(
+                    response,
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return;
+            }
+
+            // Settings can only be changed in the lobby
+            if state.games.lock().unwrap().contains_key(&user.roomId) {
+                let response = Response::from(ServerError::GameInProgress);
+                send_reply(
+                    response,
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return;
+            }
+
+            let room = match get_list_element(&user.roomId, state.rooms.clone()) {
+                Some(room) => room,
+                None => {
+                    let response = Response::from(ServerError::RoomNotFound(user.roomId.clone()));
+                    send_reply(
+                        response,
+                        state.peers.clone(),
+                        &connection_id.lock().unwrap().clone(),
+                        &request_id,
+                    );
+                    return;
+                }
+            };
+
+            let settings = match validate_room_settings(
+                settings.merge(&room.settings),
+                room.current_players,
+                state.packs.clone(),
+            ) {
+                Ok(settings) => settings,
+                Err(error) => {
+                    let response = Response::from(error);
+                    send_reply(
+                        response,
+                        state.peers.clone(),
+                        &connection_id.lock().unwrap().clone(),
+                        &request_id,
+                    );
+                    return;
+                }
+            };
+
+            match edit_list_element(&user.roomId, state.rooms.clone(), |room| {
+                room.settings = settings.clone();
+            }) {
+                Ok(_) => state.notify_room_list_changed(),
+                Err(error) => {
+                    let response = Response::from(error);
+                    send_reply(
+                        response,
+                        state.peers.clone(),
+                        &connection_id.lock().unwrap().clone(),
+                        &request_id,
+                    );
+                    return;
+                }
+            }
+
+            // Broadcast the new settings to the room
+            let broadcast_response = Response::roomSettingsChanged { settings };
+            broadcast_message_room_all(
+                broadcast_response,
+                state.peers.clone(),
+                &get_room_user_list(&user.roomId, state.users.clone()),
+            );
+            send_ack(
+                "updateRoomSettings",
+                state.peers.clone(),
+                &connection_id,
+                &request_id,
+            );
+        }
+        AuthorizedCommand::createInvite { validForSec } => {
+            info!(
+                "Create invite command from: {}",
+                &connection_id.lock().unwrap().clone()
+            );
+
+            // Return error if user is not host
+            if !user.isHost {
+                let response = Response::from(ServerError::NotHost);
+                send_reply(
+                    response,
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return;
+            }
+
+            let room = match get_list_element(&user.roomId, state.rooms.clone()) {
+                Some(room) => room,
+                None => {
+                    let response = Response::from(ServerError::RoomNotFound(user.roomId.clone()));
+                    send_reply(
+                        response,
+                        state.peers.clone(),
+                        &connection_id.lock().unwrap().clone(),
+                        &request_id,
+                    );
+                    return;
+                }
+            };
+
+            match issue_invite_token(
+                &room.id,
+                validForSec.unwrap_or(DEFAULT_INVITE_VALID_SEC),
+                state.invites.clone(),
+            ) {
+                Ok((invite, token)) => {
+                    let response = Response::inviteCreated {
+                        inviteId: invite.id,
+                        inviteToken: token,
+                        roomCode: room.code,
+                        expiresAt: invite.expires_at,
+                    };
+                    send_reply(
+                        response,
+                        state.peers.clone(),
+                        &connection_id.lock().unwrap().clone(),
+                        &request_id,
+                    );
+                }
+                Err(error) => {
+                    let response = Response::from(ServerError::Internal(error.to_string()));
+                    send_reply(
+                        response,
+                        state.peers.clone(),
+                        &connection_id.lock().unwrap().clone(),
+                        &request_id,
+                    );
+                }
+            }
+        }
+        AuthorizedCommand::revokeInvites { inviteId } => {
+            info!(
+                "Revoke invites command from: {}",
+                &connection_id.lock().unwrap().clone()
+            );
+
+            // Return error if user is not host
+            if !user.isHost {
+                let response = Response::from(ServerError::NotHost);
+                send_reply(
+                    response,
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return;
+            }
+
+            let response = Response::invitesRevoked {
+                inviteIds: revoke_invites(&user.roomId, &inviteId, state.invites.clone()),
+            };
+            send_reply(
+                response,
+                state.peers.clone(),
+                &connection_id.lock().unwrap().clone(),
+                &request_id,
+            );
         }
     }
 }
 
+// A valid invite lets the player in, otherwise the room's password has to match.
+// Returns the invite the player came in with, for the caller to redeem after joining
+fn check_room_access(
+    room: &Room,
+    password: &Option<String>,
+    invite_token: &Option<String>,
+    invites: InviteList,
+) -> Result<Option<Invite>, ServerError> {
+    let invite_error = match invite_token {
+        Some(invite_token) => match check_invite(invite_token, &room.id, invites) {
+            Ok(invite) => return Ok(Some(invite)),
+            Err(error) => Some(error),
+        },
+        None => None,
+    };
+
+    match (&room.settings.passwordHash, password) {
+        (Some(password_hash), Some(password)) if verify_password(password_hash, password) => {
+            return Ok(None)
+        }
+        (Some(_), _) if invite_error.is_none() => return Err(ServerError::InvalidPassword),
+        _ => (),
+    }
+
+    match invite_error {
+        Some(error) => Err(error),
+        None => Err(ServerError::InvalidInvite(
+            "room is invite only".to_string(),
+        )),
+    }
+}
+
+// Checks settings sent by the host, `current` is given when a room updates its settings
+fn validate_room_settings(
+    mut settings: RoomSettings,
+    current_players: i32,
+    packs: PackList,
+) -> Result<RoomSettings, ServerError> {
+    if settings.maxPlayers < 1 || settings.maxPlayers > 32 {
+        return Err(ServerError::InvalidSettings(
+            "maxPlayers must be between 1 and 32".to_string(),
+        ));
+    }
+    if settings.maxPlayers < current_players {
+        return Err(ServerError::InvalidSettings(format!(
+            "maxPlayers can't be lower than the {} players in the room",
+            current_players
+        )));
+    }
+
+    match &settings.defaultPackId {
+        Some(pack_id) if !packs.lock().unwrap().contains_key(pack_id) => {
+            return Err(ServerError::PackNotFound(pack_id.clone()));
+        }
+        _ => (),
+    }
+
+    match settings.questionDurationSec {
+        Some(duration) if !(1..=600).contains(&duration) => {
+            return Err(ServerError::InvalidSettings(
+                "questionDurationSec must be between 1 and 600".to_string(),
+            ));
+        }
+        _ => (),
+    }
+
+    // Only the hash of a new password is kept, an empty password removes it
+    match settings.password.take().as_deref() {
+        Some("") => settings.passwordHash = None,
+        Some(password) => settings.passwordHash = Some(hash_password(password)),
+        None => (),
+    }
+    settings.hasPassword = settings.passwordHash.is_some();
+
+    Ok(settings)
+}
+
+// Pack as played in the room, with the room's overrides applied
+fn apply_room_settings(mut pack: Pack, settings: &RoomSettings) -> Pack {
+    if let Some(duration) = settings.questionDurationSec {
+        for question in pack.questions.iter_mut() {
+            question.duration_sec = duration;
+        }
+    }
+    if let Some(curve) = settings.scoringMode {
+        pack.scoring.curve = curve
+    }
+    pack
+}
+
+// Sends the running game of the room to the user, nothing if the room is in the lobby
+fn send_game_state(
+    user_id: &String,
+    room_id: &String,
+    game_states: GameStateList,
+    peer_map: PeerMap,
+    request_id: &Option<String>,
+) {
+    let game_state = game_states.lock().unwrap().get(room_id).cloned();
+    if let Some(state) = game_state {
+        let game_state_response = Response::gameStateResponse {
+            phase: state.phase,
+            questionIndex: state.question_index,
+            questionCount: state.question_count,
+            question: state.question,
+            questionType: state.question_type,
+            answers: state.answers,
+            timer: state.timer,
+            paused: state.paused,
+            ownAnswer: state.player_answers.get(user_id).cloned().flatten(),
+            correctAnswer: state.correct_answer,
+            scores: state.scores,
+        };
+        send_reply(game_state_response, peer_map, user_id, request_id);
+    }
+}
+
+// Forwards a game control command to the room's game, only the host may do this
+fn execute_host_game_command(
+    command: GameCommand,
+    user: &User,
+    state: AppState,
+    connection_id: MutexId,
+    request_id: &Option<String>,
+) {
+    info!(
+        "Game control command from: {}",
+        &connection_id.lock().unwrap().clone()
+    );
+
+    // Return error if user is not host
+    if !user.isHost {
+        let response = Response::from(ServerError::NotHost);
+        send_reply(
+            response,
+            state.peers.clone(),
+            &connection_id.lock().unwrap().clone(),
+            request_id,
+        );
+        return;
+    }
+
+    // Return error if game doesn't exist
+    if !state.games.lock().unwrap().contains_key(&user.roomId) {
+        let response = Response::from(ServerError::GameNotStarted);
+        send_reply(
+            response,
+            state.peers.clone(),
+            &connection_id.lock().unwrap().clone(),
+            request_id,
+        );
+        return;
+    }
+
+    send_game_command(&command, &user.roomId, state.games.clone());
+    send_ack(
+        command.name(),
+        state.peers.clone(),
+        &connection_id,
+        request_id,
+    );
+}
+
+fn send_ack(
+    command: &str,
+    peer_map: PeerMap,
+    connection_id: &MutexId,
+    request_id: &Option<String>,
+) {
+    let response = Response::ack {
+        command: command.to_string(),
+    };
+    send_reply(
+        response,
+        peer_map,
+        &connection_id.lock().unwrap().clone(),
+        request_id,
+    );
+}
+
 fn create_room(
     id: MutexId,
     name: String,
     avatar_path: String,
-) -> Result<(User, String, Room), String> {
+    settings: RoomSettings,
+    sessions: SessionList,
+) -> Result<(User, String, Room), ServerError> {
     let new_room = Room {
         id: Uuid::new_v4().to_string(),
-        max_players: 6,
+        // Assigned when the room is added to the list
+        code: String::new(),
         host_id: id.lock().unwrap().clone(),
         current_players: 1,
+        settings,
+        pack_id: None,
     };
 
     let color: UserColors = rand::random();
@@ -968,14 +1609,10 @@ fn create_room(
         userColor: color.value(),
     };
 
-    let token = generate_token(&new_user);
+    let token = issue_session_token(&new_user.id, sessions);
     match token {
-        Ok(token) => {
-            return Ok((new_user, token, new_room));
-        }
-        Err(error) => {
-            return Err(error.to_string());
-        }
+        Ok(token) => Ok((new_user, token, new_room)),
+        Err(error) => Err(ServerError::Internal(error.to_string())),
     }
 }
 
@@ -983,25 +1620,22 @@ fn join_room(
     id: MutexId,
     name: String,
     avatar_path: String,
-    room_id: &String,
-) -> Result<(User, String), String> {
+    room_id: &str,
+    sessions: SessionList,
+) -> Result<(User, String), ServerError> {
     let color: UserColors = rand::random();
     let new_user = User {
         id: id.lock().unwrap().clone(),
         name: name.to_string(),
         avatarPath: avatar_path.to_string(),
-        roomId: room_id.clone(),
+        roomId: room_id.to_string(),
         isHost: false,
         userColor: color.value(),
     };
 
-    let token = generate_token(&new_user);
+    let token = issue_session_token(&new_user.id, sessions);
     match token {
-        Ok(token) => {
-            return Ok((new_user, token));
-        }
-        Err(error) => {
-            return Err(error.to_string());
-        }
+        Ok(token) => Ok((new_user, token)),
+        Err(error) => Err(ServerError::Internal(error.to_string())),
     }
 }
@@ -1,51 +1,112 @@
 use crate::{
+    config::{get_config, HeartbeatConfig, DEFAULT_HANDSHAKE_TIMEOUT_SEC},
+    errors::ServerError,
     handlers::{
         command_handler::{execute_authorized_command, execute_unauthorized_command},
+        http_handler::handle_http_request,
         timeout_handler::handle_user_timeout,
     },
-    helpers::parse_command,
-    models::{
-        communication::{Command, Response},
-        lobby::{Room, User},
-    },
-    server_messages::send_message,
+    helpers::{parse_command, parse_request_id},
+    http::{parse_request_head, read_request_head, PrefixedStream},
+    metrics::metrics,
+    models::communication::{Command, Response},
+    peer_queue::{peer_channel, PeerSender},
+    rate_limit::{RateLimitDecision, RateLimiter},
+    room_list::unsubscribe_room_list,
+    server_messages::{send_message, send_reply},
+    state::AppState,
 };
-use futures_channel::mpsc::{unbounded, UnboundedSender};
-use futures_util::{future, pin_mut, StreamExt, TryStreamExt};
+use futures_channel::mpsc::unbounded;
+use futures_timer::Delay;
+use futures_util::{future, pin_mut, stream, StreamExt, TryStreamExt};
 use log::{info, warn};
 use std::{
     collections::HashMap,
     net::SocketAddr,
     sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::timeout,
 };
-use tokio::net::TcpStream;
 use tokio_native_tls::TlsAcceptor;
 use tungstenite::Message;
 use uuid::Uuid;
 
-type Tx = UnboundedSender<Message>;
-type TxTimeout = UnboundedSender<bool>;
-type PeerMap = Arc<Mutex<HashMap<String, Tx>>>;
-type UserList = Arc<Mutex<Vec<User>>>;
-type RoomList = Arc<Mutex<Vec<Room>>>;
-type GameList = Arc<Mutex<HashMap<String, Tx>>>;
-type UserTimeoutList = Arc<Mutex<HashMap<String, TxTimeout>>>;
-type Lists = (PeerMap, UserList, RoomList, GameList, UserTimeoutList);
+type PeerMap = Arc<Mutex<HashMap<String, PeerSender>>>;
 type MutexId = Arc<Mutex<String>>;
+type TlsAcceptorHandle = Arc<Mutex<TlsAcceptor>>;
 
 pub async fn handle_connection(
-    lists: Lists,
+    state: AppState,
     raw_stream: TcpStream,
     addr: SocketAddr,
-    // acceptor: TlsAcceptor,
+    acceptor: Option<TlsAcceptorHandle>,
 ) {
     info!("Incoming TCP connection from: {}", &addr);
 
-    // let tls_stream = match acceptor.accept(raw_stream).await {
-    //     Ok(res) => res,
-    //     Err(_) => return,
-    // };
-    let ws_stream = match tokio_tungstenite::accept_async(raw_stream).await {
+    match acceptor {
+        Some(acceptor) => {
+            let acceptor = acceptor.lock().unwrap().clone();
+            let handshake_timeout = get_config()
+                .tls
+                .as_ref()
+                .map_or(DEFAULT_HANDSHAKE_TIMEOUT_SEC, |tls| {
+                    tls.handshake_timeout_sec
+                });
+            match timeout(
+                Duration::from_secs(handshake_timeout),
+                acceptor.accept(raw_stream),
+            )
+            .await
+            {
+                Ok(Ok(tls_stream)) => route_stream(state, tls_stream, addr).await,
+                Ok(Err(error)) => warn!("TLS handshake with {} error: {}", addr, error),
+                Err(_) => warn!("Timed out waiting for TLS handshake from {}", addr),
+            }
+        }
+        None => route_stream(state, raw_stream, addr).await,
+    }
+}
+
+// WebSocket upgrades go to the game server, any other HTTP request to the HTTP endpoints
+async fn route_stream<S>(state: AppState, mut stream: S, addr: SocketAddr)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let head = match timeout(Duration::from_secs(10), read_request_head(&mut stream)).await {
+        Ok(Ok(head)) => head,
+        Ok(Err(error)) => {
+            warn!("Could not read request from {}: {}", addr, error);
+            return;
+        }
+        Err(_) => {
+            warn!("Timed out reading request from {}", addr);
+            return;
+        }
+    };
+
+    let request = match parse_request_head(&head) {
+        Ok(request) => request,
+        Err(error) => {
+            warn!("Bad request from {}: {}", addr, error);
+            return;
+        }
+    };
+
+    match request.websocket_upgrade {
+        true => handle_websocket(state, PrefixedStream::new(head, stream), addr).await,
+        false => handle_http_request(request, stream, addr, state.clone()).await,
+    }
+}
+
+async fn handle_websocket<S>(state: AppState, stream: S, addr: SocketAddr)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let ws_stream = match tokio_tungstenite::accept_async(stream).await {
         Ok(stream) => stream,
         Err(error) => {
             warn!("Handshake with {} error: {}", addr, error);
@@ -56,49 +117,89 @@ pub async fn handle_connection(
 
     let connection_id = MutexId::new(Mutex::new(Uuid::new_v4().to_string()));
 
-    let (tx, rx) = unbounded();
-    lists
-        .0
+    let (tx, rx) = peer_channel(&get_config().queue);
+    state
+        .peers
         .lock()
         .unwrap()
         .insert(connection_id.lock().unwrap().clone(), tx);
 
+    let heartbeat = get_config().heartbeat.clone();
+    send_message(
+        Response::connectionInfoResponse {
+            heartbeatIntervalSec: heartbeat.interval_sec,
+            maxMissedPongs: heartbeat.max_missed_pongs,
+        },
+        state.peers.clone(),
+        &connection_id.lock().unwrap().clone(),
+    );
+
+    let missed_pongs = Arc::new(Mutex::new(0u32));
+    let rate_limiter = Mutex::new(RateLimiter::new(&get_config().rate_limit));
+
     let (outgoing, incoming) = ws_stream.split();
 
     let broadcast_incoming = incoming.try_for_each(|msg| {
-        match parse_command(&msg) {
+        match msg {
+            Message::Pong(_) => {
+                *missed_pongs.lock().unwrap() = 0;
+                return future::ok(());
+            }
+            // Pings are answered by tungstenite, close frames end the stream
+            Message::Ping(_) | Message::Close(_) | Message::Frame(_) => {
+                return future::ok(());
+            }
+            _ => (),
+        }
+
+        let command = parse_command(&msg);
+        let (command_name, request_id) = match &command {
+            Ok(command) => (command.name(), command.request_id()),
+            Err(_) => ("invalid", parse_request_id(&msg)),
+        };
+        match rate_limiter.lock().unwrap().check(command_name) {
+            RateLimitDecision::Allowed => metrics().record_command(command_name),
+            RateLimitDecision::Limited(retry_after) => {
+                send_reply(
+                    Response::from(ServerError::RateLimited {
+                        command: command_name.to_string(),
+                        retry_after_ms: retry_after.as_millis().min(u64::MAX as u128) as u64,
+                    }),
+                    state.peers.clone(),
+                    &connection_id.lock().unwrap().clone(),
+                    &request_id,
+                );
+                return future::ok(());
+            }
+            RateLimitDecision::Disconnect => {
+                warn!(
+                    "{} keeps exceeding rate limits ({}), disconnecting",
+                    addr, command_name
+                );
+                return future::err(tungstenite::Error::ConnectionClosed);
+            }
+        }
+
+        match command {
             Ok(command) => match command {
                 Command::UnauthorizedCommand(command) => execute_unauthorized_command(
                     command,
-                    (
-                        lists.0.clone(),
-                        lists.1.clone(),
-                        lists.2.clone(),
-                        lists.3.clone(),
-                    ),
-                    connection_id.clone(),
-                ),
-                Command::CommandTokenPair(command) => execute_authorized_command(
-                    command,
-                    (
-                        lists.0.clone(),
-                        lists.1.clone(),
-                        lists.2.clone(),
-                        lists.3.clone(),
-                    ),
+                    state.clone(),
                     connection_id.clone(),
+                    &addr,
                 ),
+                Command::CommandTokenPair(command) => {
+                    execute_authorized_command(command, state.clone(), connection_id.clone())
+                }
             },
             Err(error) => {
                 warn!("Error parsing command!: {}", error);
-                let response = Response::errorResponse {
-                    errorText: error.to_string(),
-                    errorCode: 0,
-                };
-                send_message(
+                let response = Response::from(error);
+                send_reply(
                     response,
-                    lists.0.clone(),
+                    state.peers.clone(),
                     &connection_id.lock().unwrap().clone(),
+                    &request_id,
                 );
             }
         }
@@ -107,72 +208,122 @@ pub async fn handle_connection(
         future::ok(())
     });
 
-    let receive_from_others = rx.map(Ok).forward(outgoing);
+    let receive_from_others = stream::unfold(rx, |rx| async move {
+        rx.recv().await.map(|message| (Ok(message), rx))
+    })
+    .forward(outgoing);
+
+    let check_liveness = check_liveness(
+        heartbeat,
+        missed_pongs.clone(),
+        state.peers.clone(),
+        connection_id.clone(),
+    );
 
-    pin_mut!(broadcast_incoming, receive_from_others);
-    future::select(broadcast_incoming, receive_from_others).await;
+    pin_mut!(broadcast_incoming, receive_from_others, check_liveness);
+    future::select(
+        broadcast_incoming,
+        future::select(receive_from_others, check_liveness),
+    )
+    .await;
 
     info!("{} disconnected", &addr);
 
-    let room_id = match lists
-        .1
+    let room_id = state
+        .users
         .lock()
         .unwrap()
         .iter()
         .find(|user| user.id == connection_id.lock().unwrap().clone())
-    {
-        Some(user) => Some(user.roomId.clone()),
-        None => None,
-    };
+        .map(|user| user.roomId.clone());
 
-    let user_id = match lists
-        .1
+    let user_id = state
+        .users
         .lock()
         .unwrap()
         .iter()
         .find(|user| user.id == connection_id.lock().unwrap().clone())
-    {
-        Some(user) => Some(user.id.clone()),
-        None => None,
-    };
+        .map(|user| user.id.clone());
 
-    match user_id {
-        Some(user_id) => {
-            println!("Removing user");
-            // lists.1.lock().unwrap().remove(index);
-
-            let (tx_timeout, rx_timeout) = unbounded();
-            let timeout = lists.4.lock().unwrap().get(&user_id).cloned();
-
-            match timeout {
-                Some(tx) => {
-                    match tx.unbounded_send(false) {
-                        Ok(_) => (),
-                        Err(error) => println!("Could not send: {}", error),
-                    }
-                    lists.4.lock().unwrap().remove(&user_id);
-                }
-                None => (),
-            }
+    if let Some(user_id) = user_id {
+        println!("Removing user");
+        // state.users.lock().unwrap().remove(index);
+
+        let (tx_timeout, rx_timeout) = unbounded();
+        let timeout = state.user_timeouts.lock().unwrap().get(&user_id).cloned();
 
-            lists.4.lock().unwrap().insert(user_id.clone(), tx_timeout);
-            tokio::spawn(handle_user_timeout(
-                user_id.clone(),
-                room_id.unwrap().clone(),
-                (lists.0.clone(), lists.1.clone(), lists.2.clone()),
-                rx_timeout,
-            ));
+        if let Some(tx) = timeout {
+            match tx.unbounded_send(false) {
+                Ok(_) => (),
+                Err(error) => println!("Could not send: {}", error),
+            }
+            state.user_timeouts.lock().unwrap().remove(&user_id);
         }
-        None => (),
+
+        state
+            .user_timeouts
+            .lock()
+            .unwrap()
+            .insert(user_id.clone(), tx_timeout);
+        tokio::spawn(handle_user_timeout(
+            user_id.clone(),
+            room_id.unwrap().clone(),
+            state.clone(),
+            rx_timeout,
+        ));
     }
 
+    unsubscribe_room_list(
+        &connection_id.lock().unwrap().clone(),
+        state.room_list_subscriptions.clone(),
+    );
+
     println!("Wanna remove connection 1.1");
     // Remove connection from list
-    lists
-        .0
+    state
+        .peers
         .lock()
         .unwrap()
         .remove(&connection_id.lock().unwrap().clone());
 
     println!("Removed connection 1.2");
 }
+
+// Resolves when the peer has missed too many pongs, which drops the connection
+// the same way an unclean close would
+async fn check_liveness(
+    heartbeat: HeartbeatConfig,
+    missed_pongs: Arc<Mutex<u32>>,
+    peer_map: PeerMap,
+    connection_id: MutexId,
+) {
+    loop {
+        Delay::new(Duration::from_secs(heartbeat.interval_sec)).await;
+
+        {
+            let mut missed_pongs = missed_pongs.lock().unwrap();
+            if *missed_pongs >= heartbeat.max_missed_pongs {
+                warn!(
+                    "Connection {} missed {} pongs, dropping",
+                    connection_id.lock().unwrap(),
+                    missed_pongs
+                );
+                return;
+            }
+            *missed_pongs += 1;
+        }
+
+        let tx = peer_map
+            .lock()
+            .unwrap()
+            .get(&connection_id.lock().unwrap().clone())
+            .cloned();
+        match tx {
+            Some(tx) => match tx.send(Message::Ping(Vec::new())) {
+                Ok(_) => (),
+                Err(_) => return,
+            },
+            None => return,
+        }
+    }
+}
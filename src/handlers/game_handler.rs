@@ -1,36 +1,87 @@
 use crate::{
-    helpers::parse_game_command,
+    helpers::{get_room_user_list, parse_game_command},
+    metrics::metrics,
     models::{
         communication::Response,
-        game::Pack,
-        lobby::{Room, User},
+        game::{AnswerPayload, GameCommand, GamePhase, GameState, Pack, QuestionKind, Standing},
+        lobby::User,
     },
+    peer_queue::PeerSender,
     server_messages::broadcast_message_room_all,
+    state::AppState,
+    storage::{record_game, AnswerRecord, GameRecord, PlayerRecord},
 };
-use futures_channel::mpsc::{unbounded, UnboundedSender};
+use chrono::Utc;
+use futures_channel::mpsc::unbounded;
 use futures_timer::Delay;
-use futures_util::{future, pin_mut, StreamExt};
+use futures_util::{
+    future::{self, Either},
+    pin_mut, StreamExt,
+};
+use log::{info, warn};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
-use tungstenite::Message;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+type PeerMap = Arc<Mutex<HashMap<String, PeerSender>>>;
+type GameStateList = Arc<Mutex<HashMap<String, GameState>>>;
+
+// Host controls and the pausable clock shared between the command receiver and the game loop
+#[derive(Default)]
+struct GameControl {
+    paused_since: Option<Instant>,
+    paused_total: Duration,
+    skip_requested: bool,
+    end_requested: bool,
+}
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<HashMap<String, Tx>>>;
-type UserList = Arc<Mutex<Vec<User>>>;
-type RoomList = Arc<Mutex<Vec<Room>>>;
-type GameList = Arc<Mutex<HashMap<String, Tx>>>;
-type Lists = (PeerMap, UserList, RoomList, GameList);
+impl GameControl {
+    fn is_paused(&self) -> bool {
+        self.paused_since.is_some()
+    }
 
-pub async fn handle_game(lists: Lists, user_list: Vec<User>, room_id: String, pack: Pack) {
+    // Returns false if the game was not paused
+    fn resume(&mut self) -> bool {
+        match self.paused_since.take() {
+            Some(paused_since) => {
+                self.paused_total += paused_since.elapsed();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Time passed since `since`, not counting time spent paused
+    fn unpaused_elapsed(&self, since: Instant) -> Duration {
+        let paused_now = match self.paused_since {
+            Some(paused_since) => paused_since.elapsed(),
+            None => Duration::ZERO,
+        };
+        since
+            .elapsed()
+            .saturating_sub(self.paused_total + paused_now)
+    }
+}
+
+pub async fn handle_game(
+    state: AppState,
+    user_list: Vec<User>,
+    room_id: String,
+    pack_id: String,
+    pack: Pack,
+) {
+    let started_at = Utc::now();
     let (tx_room, rx_room) = unbounded();
-    lists.3.lock().unwrap().insert(room_id.clone(), tx_room);
+    state.games.lock().unwrap().insert(room_id.clone(), tx_room);
+    state.notify_room_list_changed();
 
-    let answers = Arc::new(Mutex::new(HashMap::<String, i32>::new()));
+    let answers = Arc::new(Mutex::new(HashMap::<String, Option<AnswerPayload>>::new()));
     user_list.iter().for_each(|user| {
-        answers.lock().unwrap().insert(user.id.clone(), -1);
+        answers.lock().unwrap().insert(user.id.clone(), None);
     });
 
     let scores = Arc::new(Mutex::new(HashMap::<String, i32>::new()));
@@ -38,76 +89,699 @@ pub async fn handle_game(lists: Lists, user_list: Vec<User>, room_id: String, pa
         scores.lock().unwrap().insert(user.id.clone(), 0);
     });
 
+    let correct_counts = Arc::new(Mutex::new(HashMap::<String, i32>::new()));
+    // Every judged answer, written to the database when the game is over
+    let answer_records = Arc::new(Mutex::new(Vec::<AnswerRecord>::new()));
+
+    state.game_states.lock().unwrap().insert(
+        room_id.clone(),
+        GameState {
+            phase: GamePhase::question,
+            question_index: 0,
+            question_count: pack.questions.len() as i32,
+            question: String::new(),
+            question_type: String::new(),
+            answers: Vec::new(),
+            timer: 0,
+            paused: false,
+            player_answers: answers.lock().unwrap().clone(),
+            correct_answer: None,
+            scores: scores.lock().unwrap().clone(),
+        },
+    );
+
+    // Time it took each user to answer, counted from the answersResponse broadcast
+    let answer_times = Arc::new(Mutex::new(HashMap::<String, Duration>::new()));
+    // Set while answers are accepted for the current question
+    let answers_opened_at = Arc::new(Mutex::new(None::<Instant>));
+    let control = Arc::new(Mutex::new(GameControl::default()));
+    // Wakes the game loop whenever an answer or a host command comes in
+    let game_notify = Arc::new(Notify::new());
+
+    let peer_map = state.peers.clone();
+    let game_states = state.game_states.clone();
+    let database = state.database.clone();
+    let pack_name = pack.name.clone();
+    let room_users = user_list.clone();
+    let users = state.users.clone();
+    // The game loop takes `state`, the lobby still needs it once the game is over
+    let lobby_state = state.clone();
     let receive_future = rx_room.for_each(|msg| {
         match parse_game_command(&msg) {
-            Ok(command) => match answers.lock().unwrap().get_mut(&command.0) {
-                Some(user_answer) => *user_answer = command.1,
-                None => (),
-            },
+            Ok(GameCommand::writeAnswer { user_id, answer }) => {
+                let opened_at = *answers_opened_at.lock().unwrap();
+                let control = control.lock().unwrap();
+                match (answers.lock().unwrap().get_mut(&user_id), opened_at) {
+                    (Some(user_answer), Some(opened_at)) if !control.is_paused() => {
+                        *user_answer = Some(answer.clone());
+                        update_game_state(&game_states, &room_id, |state| {
+                            state.player_answers.insert(user_id.clone(), Some(answer));
+                        });
+                        let answer_time = control.unpaused_elapsed(opened_at);
+                        metrics().observe_answer_latency(answer_time);
+                        answer_times.lock().unwrap().insert(user_id, answer_time);
+                    }
+                    _ => (),
+                }
+            }
+            Ok(GameCommand::pauseGame {}) => {
+                let mut control = control.lock().unwrap();
+                if !control.is_paused() {
+                    control.paused_since = Some(Instant::now());
+                    update_game_state(&game_states, &room_id, |state| state.paused = true);
+                    broadcast_message_room_all(
+                        Response::gamePausedResponse {},
+                        peer_map.clone(),
+                        &get_room_user_list(&room_id, users.clone()),
+                    );
+                }
+            }
+            Ok(GameCommand::resumeGame {}) => {
+                if control.lock().unwrap().resume() {
+                    update_game_state(&game_states, &room_id, |state| state.paused = false);
+                    broadcast_message_room_all(
+                        Response::gameResumedResponse {},
+                        peer_map.clone(),
+                        &get_room_user_list(&room_id, users.clone()),
+                    );
+                }
+            }
+            Ok(GameCommand::skipQuestion {}) => {
+                // Skipping resumes a paused game, the next question runs on the normal clock
+                let mut control = control.lock().unwrap();
+                if control.resume() {
+                    update_game_state(&game_states, &room_id, |state| state.paused = false);
+                    broadcast_message_room_all(
+                        Response::gameResumedResponse {},
+                        peer_map.clone(),
+                        &get_room_user_list(&room_id, users.clone()),
+                    );
+                }
+                control.skip_requested = true;
+            }
+            Ok(GameCommand::endGame {}) => control.lock().unwrap().end_requested = true,
             Err(_) => (),
         }
+        game_notify.notify_one();
         future::ready(())
     });
 
     let answers_clone = answers.clone();
     let scores_clone = scores.clone();
+    let correct_counts_clone = correct_counts.clone();
+    let answer_records_clone = answer_records.clone();
+    let answer_times_clone = answer_times.clone();
+    let answers_opened_at_clone = answers_opened_at.clone();
+    let control_clone = control.clone();
+    let game_notify_clone = game_notify.clone();
+    let room_id_clone = room_id.clone();
+    let users_clone = users.clone();
     let game_process_future = async move {
         let mut questions_index = 0;
         while questions_index < pack.questions.len() {
             let question = pack.questions.get(questions_index).unwrap();
+            questions_index += 1;
+
+            // Players who joined late take part from the next question on
+            let user_list = get_room_user_list(&room_id_clone, users_clone.clone());
+            user_list.iter().for_each(|user| {
+                answers_clone
+                    .lock()
+                    .unwrap()
+                    .entry(user.id.clone())
+                    .or_insert(None);
+                scores_clone
+                    .lock()
+                    .unwrap()
+                    .entry(user.id.clone())
+                    .or_insert(0);
+            });
+            update_game_state(&state.game_states, &room_id_clone, |state| {
+                state.scores = scores_clone.lock().unwrap().clone();
+            });
 
             let question_announcement = Response::questionResponse {
                 question: question.text.clone(),
             };
-            broadcast_message_room_all(question_announcement, lists.0.clone(), &user_list);
-            Delay::new(Duration::from_secs(2)).await;
-
-            let answers_and_timer = Response::answersResponse {
-                answers: question.answers.clone(),
-                timer: question.duration_sec,
-            };
-            broadcast_message_room_all(answers_and_timer, lists.0.clone(), &user_list);
+            broadcast_message_room_all(question_announcement, state.peers.clone(), &user_list);
+            update_game_state(&state.game_states, &room_id_clone, |state| {
+                state.phase = GamePhase::question;
+                state.question_index = questions_index as i32 - 1;
+                state.question = question.text.clone();
+                state.question_type = question.kind.name();
+                state.answers = Vec::new();
+                state.timer = question.duration_sec;
+                state.correct_answer = None;
+                state
+                    .player_answers
+                    .iter_mut()
+                    .for_each(|answer| *answer.1 = None);
+            });
+            game_wait(
+                Duration::from_secs(2),
+                &control_clone,
+                &game_notify_clone,
+                |control| control.skip_requested || control.end_requested,
+            )
+            .await;
 
+            let mut closed_early = false;
             let mut timer_iter = question.duration_sec;
-            while timer_iter >= 0 {
-                let timer_response = Response::timerResponse { timer: timer_iter };
-                broadcast_message_room_all(timer_response, lists.0.clone(), &user_list);
-                Delay::new(Duration::from_secs(1)).await;
+            if !is_interrupted(&control_clone) {
+                let answers_and_timer = Response::answersResponse {
+                    questionType: question.kind.name(),
+                    answers: question.kind.answers(),
+                    timer: question.duration_sec,
+                };
+                broadcast_message_room_all(answers_and_timer, state.peers.clone(), &user_list);
+                *answers_opened_at_clone.lock().unwrap() = Some(Instant::now());
+                update_game_state(&state.game_states, &room_id_clone, |state| {
+                    state.phase = GamePhase::answering;
+                    state.answers = question.kind.answers();
+                });
+                control_clone.lock().unwrap().paused_total = Duration::ZERO;
+
+                while timer_iter >= 0 {
+                    let timer_response = Response::timerResponse { timer: timer_iter };
+                    broadcast_message_room_all(timer_response, state.peers.clone(), &user_list);
+                    update_game_state(&state.game_states, &room_id_clone, |state| {
+                        state.timer = timer_iter
+                    });
 
-                timer_iter -= 1;
+                    // Wait out the tick, checking on every answer whether the round can close
+                    let wait_result = game_wait(
+                        Duration::from_secs(1),
+                        &control_clone,
+                        &game_notify_clone,
+                        |control| {
+                            if control.skip_requested || control.end_requested {
+                                return true;
+                            }
+                            closed_early = pack.end_when_all_answered
+                                && all_players_answered(
+                                    &answers_clone.lock().unwrap(),
+                                    state.peers.clone(),
+                                );
+                            closed_early
+                        },
+                    )
+                    .await;
+                    if !wait_result {
+                        break;
+                    }
+
+                    timer_iter -= 1;
+                }
+                *answers_opened_at_clone.lock().unwrap() = None;
             }
 
-            let correct_answer_response = Response::correctAnswerResponse {
-                answers: answers_clone.lock().unwrap().clone(),
-                correctAnswer: question.correct_answer,
-            };
-            broadcast_message_room_all(correct_answer_response, lists.0.clone(), &user_list);
+            if control_clone.lock().unwrap().end_requested {
+                break;
+            }
 
-            answers_clone.lock().unwrap().iter().for_each(|answer| {
-                if answer.1 == &question.correct_answer {
-                    *scores_clone.lock().unwrap().get_mut(answer.0).unwrap() += 100;
+            let skip_requested = std::mem::take(&mut control_clone.lock().unwrap().skip_requested);
+            if skip_requested {
+                broadcast_message_room_all(
+                    Response::questionSkippedResponse {},
+                    state.peers.clone(),
+                    &user_list,
+                );
+                // Skipped questions reveal nothing, but the answering round is over
+                update_game_state(&state.game_states, &room_id_clone, |state| {
+                    state.phase = GamePhase::reveal;
+                    state.answers = Vec::new();
+                    state.timer = 0;
+                    state.correct_answer = None;
+                    state
+                        .player_answers
+                        .iter_mut()
+                        .for_each(|answer| *answer.1 = None);
+                });
+            } else {
+                if closed_early {
+                    let closed_early_response = Response::closedEarlyResponse { timer: timer_iter };
+                    broadcast_message_room_all(
+                        closed_early_response,
+                        state.peers.clone(),
+                        &user_list,
+                    );
                 }
-            });
 
-            let scores_response = Response::scoresResponse {
-                scores: scores_clone.lock().unwrap().clone(),
-            };
-            broadcast_message_room_all(scores_response, lists.0.clone(), &user_list);
+                let correct_answer_response = Response::correctAnswerResponse {
+                    answers: answers_clone.lock().unwrap().clone(),
+                    correctAnswer: question.kind.correct_answer(),
+                };
+                broadcast_message_room_all(
+                    correct_answer_response,
+                    state.peers.clone(),
+                    &user_list,
+                );
+
+                // Faster correct answers earn more, following the pack's scoring curve
+                let question_duration = Duration::from_secs(question.duration_sec.max(0) as u64);
+                let correct_users = judge_answers(&question.kind, &answers_clone.lock().unwrap());
+                let mut points_earned = HashMap::<String, i32>::new();
+                answers_clone.lock().unwrap().iter().for_each(|answer| {
+                    let answer_time = answer_times_clone.lock().unwrap().get(answer.0).cloned();
+                    let mut points = 0;
+                    if correct_users.contains(answer.0) {
+                        let answer_time = answer_time.unwrap_or(question_duration);
+                        points = pack.scoring.points(
+                            question_duration.saturating_sub(answer_time),
+                            question_duration,
+                        );
+                        *scores_clone.lock().unwrap().get_mut(answer.0).unwrap() += points;
+                        *correct_counts_clone
+                            .lock()
+                            .unwrap()
+                            .entry(answer.0.clone())
+                            .or_insert(0) += 1;
+                    }
+                    points_earned.insert(answer.0.clone(), points);
+                    answer_records_clone.lock().unwrap().push(AnswerRecord {
+                        question_index: questions_index as i32 - 1,
+                        user_id: answer.0.clone(),
+                        answer: answer
+                            .1
+                            .as_ref()
+                            .map(|answer| serde_json::to_string(answer).unwrap()),
+                        correct: correct_users.contains(answer.0),
+                        points,
+                        response_ms: answer_time.map(|time| time.as_millis() as i64),
+                    });
+                });
+
+                let scores_response = Response::scoresResponse {
+                    scores: scores_clone.lock().unwrap().clone(),
+                    pointsEarned: points_earned,
+                };
+                broadcast_message_room_all(scores_response, state.peers.clone(), &user_list);
+                update_game_state(&state.game_states, &room_id_clone, |state| {
+                    state.phase = GamePhase::reveal;
+                    state.correct_answer = Some(question.kind.correct_answer());
+                    state.scores = scores_clone.lock().unwrap().clone();
+                });
+            }
 
             answers_clone
                 .lock()
                 .unwrap()
                 .iter_mut()
-                .for_each(|answer| *answer.1 = -1);
-
-            Delay::new(Duration::from_secs(2)).await;
+                .for_each(|answer| *answer.1 = None);
+            answer_times_clone.lock().unwrap().clear();
 
-            questions_index += 1;
+            game_wait(
+                Duration::from_secs(2),
+                &control_clone,
+                &game_notify_clone,
+                |control| control.end_requested,
+            )
+            .await;
         }
     };
 
     pin_mut!(receive_future, game_process_future);
     future::select(receive_future, game_process_future).await;
 
-    lists.3.lock().unwrap().remove(&room_id);
+    let standings = rank_standings(&scores.lock().unwrap(), &correct_counts.lock().unwrap());
+    let winners = standings
+        .iter()
+        .filter(|standing| standing.rank == 1)
+        .map(|standing| standing.userId.clone())
+        .collect();
+    let ended_early = control.lock().unwrap().end_requested;
+    let final_users = get_room_user_list(&room_id, users.clone());
+
+    let game_record = GameRecord {
+        id: Uuid::new_v4().to_string(),
+        room_id: room_id.clone(),
+        pack_id,
+        pack_name,
+        started_at: started_at.to_rfc3339(),
+        ended_at: Utc::now().to_rfc3339(),
+        ended_early,
+        players: standings
+            .iter()
+            .map(|standing| PlayerRecord {
+                user_id: standing.userId.clone(),
+                name: final_users
+                    .iter()
+                    .chain(room_users.iter())
+                    .find(|user| user.id == standing.userId)
+                    .map(|user| user.name.clone())
+                    .unwrap_or_default(),
+                score: standing.score,
+                correct_answers: standing.correctAnswers,
+                rank: standing.rank,
+            })
+            .collect(),
+        answers: std::mem::take(&mut answer_records.lock().unwrap()),
+    };
+    // SQLite blocks, keep it off the async worker threads
+    let game_id = game_record.id.clone();
+    let recorded = tokio::task::spawn_blocking(move || {
+        record_game(&mut database.lock().unwrap(), &game_record)
+    })
+    .await;
+    match recorded {
+        Ok(Ok(_)) => info!("Recorded game {} in room {}", &game_id, &room_id),
+        Ok(Err(error)) => warn!("Could not record game in room {}: {}", &room_id, error),
+        Err(error) => warn!("Recording game in room {} failed: {}", &room_id, error),
+    }
+
+    let game_over_response = Response::gameOverResponse {
+        standings,
+        winners,
+        endedEarly: ended_early,
+    };
+    broadcast_message_room_all(game_over_response, peer_map, &final_users);
+
+    // Back to the lobby: with the game gone from the list the host can start another one.
+    // Only done once the results are queued, drain waits for the list to empty before
+    // closing connections
+    lobby_state.games.lock().unwrap().remove(&room_id);
+    lobby_state.notify_room_list_changed();
+    game_states.lock().unwrap().remove(&room_id);
+}
+
+// Orders players by score, players with equal scores share a rank ("1, 1, 3")
+fn rank_standings(
+    scores: &HashMap<String, i32>,
+    correct_counts: &HashMap<String, i32>,
+) -> Vec<Standing> {
+    let mut standings: Vec<Standing> = scores
+        .iter()
+        .map(|(user_id, score)| Standing {
+            rank: 0,
+            userId: user_id.clone(),
+            score: *score,
+            correctAnswers: correct_counts.get(user_id).cloned().unwrap_or(0),
+        })
+        .collect();
+    standings.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.userId.cmp(&b.userId)));
+
+    let mut previous_score = None;
+    let mut previous_rank = 0;
+    for (index, standing) in standings.iter_mut().enumerate() {
+        if previous_score != Some(standing.score) {
+            previous_rank = index as i32 + 1;
+            previous_score = Some(standing.score);
+        }
+        standing.rank = previous_rank;
+    }
+
+    standings
+}
+
+fn update_game_state<F>(game_states: &GameStateList, room_id: &String, function: F)
+where
+    F: FnOnce(&mut GameState),
+{
+    if let Some(state) = game_states.lock().unwrap().get_mut(room_id) {
+        function(state)
+    }
+}
+
+fn is_interrupted(control: &Arc<Mutex<GameControl>>) -> bool {
+    let control = control.lock().unwrap();
+    control.skip_requested || control.end_requested
+}
+
+// Waits for `duration` of unpaused game time, re-checking `interrupt` every time the game is
+// notified. Returns false if the wait was interrupted before the time ran out
+async fn game_wait<F>(
+    duration: Duration,
+    control: &Arc<Mutex<GameControl>>,
+    notify: &Notify,
+    mut interrupt: F,
+) -> bool
+where
+    F: FnMut(&GameControl) -> bool,
+{
+    let mut remaining = duration;
+    loop {
+        let is_paused = {
+            let control = control.lock().unwrap();
+            if interrupt(&control) {
+                return false;
+            }
+            control.is_paused()
+        };
+
+        if is_paused {
+            notify.notified().await;
+            continue;
+        }
+
+        let started = Instant::now();
+        let delay = Delay::new(remaining);
+        let notified = notify.notified();
+        pin_mut!(delay, notified);
+        match future::select(delay, notified).await {
+            Either::Left(_) => return true,
+            Either::Right(_) => remaining = remaining.saturating_sub(started.elapsed()),
+        }
+    }
+}
+
+// Disconnected players don't hold the round open
+fn all_players_answered(
+    answers: &HashMap<String, Option<AnswerPayload>>,
+    peer_map: PeerMap,
+) -> bool {
+    let peers = peer_map.lock().unwrap();
+    let mut connected_answers = answers
+        .iter()
+        .filter(|(user_id, _)| peers.contains_key(*user_id))
+        .peekable();
+
+    connected_answers.peek().is_some() && connected_answers.all(|(_, answer)| answer.is_some())
+}
+
+// Returns ids of the users whose answer counts as correct for the question
+fn judge_answers(
+    kind: &QuestionKind,
+    answers: &HashMap<String, Option<AnswerPayload>>,
+) -> HashSet<String> {
+    let given_answers = answers
+        .iter()
+        .filter_map(|(user_id, answer)| answer.as_ref().map(|answer| (user_id, answer)));
+
+    match kind {
+        QuestionKind::singleChoice { correct_answer, .. } => given_answers
+            .filter(|(_, answer)| answer == &&AnswerPayload::choice(*correct_answer))
+            .map(|(user_id, _)| user_id.clone())
+            .collect(),
+        QuestionKind::trueFalse { correct_answer } => given_answers
+            .filter(|(_, answer)| answer == &&AnswerPayload::boolean(*correct_answer))
+            .map(|(user_id, _)| user_id.clone())
+            .collect(),
+        QuestionKind::multiSelect {
+            correct_answers, ..
+        } => {
+            let correct: HashSet<i32> = correct_answers.iter().cloned().collect();
+            given_answers
+                .filter(|(_, answer)| {
+                    let chosen: HashSet<i32> = match answer {
+                        AnswerPayload::choice(number) => HashSet::from([*number]),
+                        AnswerPayload::choices(numbers) => numbers.iter().cloned().collect(),
+                        _ => return false,
+                    };
+                    chosen == correct
+                })
+                .map(|(user_id, _)| user_id.clone())
+                .collect()
+        }
+        QuestionKind::numeric { correct_value } => {
+            // Closest estimate wins, everyone tied for closest is correct
+            let distances: Vec<(&String, f64)> = given_answers
+                .filter_map(|(user_id, answer)| {
+                    answer
+                        .as_number()
+                        .map(|value| (user_id, (value - correct_value).abs()))
+                })
+                .collect();
+            let closest = distances
+                .iter()
+                .map(|(_, distance)| *distance)
+                .fold(f64::INFINITY, f64::min);
+
+            distances
+                .iter()
+                .filter(|(_, distance)| *distance <= closest)
+                .map(|(user_id, _)| (*user_id).clone())
+                .collect()
+        }
+        QuestionKind::freeText { accepted_answers } => {
+            let accepted: HashSet<String> = accepted_answers
+                .iter()
+                .map(|text| normalize_text_answer(text))
+                .collect();
+            given_answers
+                .filter(|(_, answer)| match answer {
+                    AnswerPayload::text(text) => accepted.contains(&normalize_text_answer(text)),
+                    _ => false,
+                })
+                .map(|(user_id, _)| user_id.clone())
+                .collect()
+        }
+    }
+}
+
+fn normalize_text_answer(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::game::Answer;
+
+    fn answers(given: &[(&str, Option<AnswerPayload>)]) -> HashMap<String, Option<AnswerPayload>> {
+        given
+            .iter()
+            .map(|(user_id, answer)| (user_id.to_string(), answer.clone()))
+            .collect()
+    }
+
+    fn correct(users: &[&str]) -> HashSet<String> {
+        users.iter().map(|user_id| user_id.to_string()).collect()
+    }
+
+    fn options(count: i32) -> Vec<Answer> {
+        (0..count)
+            .map(|number| Answer {
+                number,
+                text: number.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn judges_single_choice() {
+        let kind = QuestionKind::singleChoice {
+            answers: options(3),
+            correct_answer: 2,
+        };
+        let given = answers(&[
+            ("a", Some(AnswerPayload::choice(2))),
+            ("b", Some(AnswerPayload::choice(1))),
+            ("c", None),
+            ("d", Some(AnswerPayload::text("2".to_string()))),
+        ]);
+        assert_eq!(judge_answers(&kind, &given), correct(&["a"]));
+    }
+
+    #[test]
+    fn judges_true_false() {
+        let kind = QuestionKind::trueFalse {
+            correct_answer: true,
+        };
+        let given = answers(&[
+            ("a", Some(AnswerPayload::boolean(true))),
+            ("b", Some(AnswerPayload::boolean(false))),
+        ]);
+        assert_eq!(judge_answers(&kind, &given), correct(&["a"]));
+    }
+
+    #[test]
+    fn multi_select_needs_exactly_the_correct_set() {
+        let kind = QuestionKind::multiSelect {
+            answers: options(4),
+            correct_answers: vec![0, 2],
+        };
+        let given = answers(&[
+            ("a", Some(AnswerPayload::choices(vec![2, 0]))),
+            ("b", Some(AnswerPayload::choices(vec![0]))),
+            ("c", Some(AnswerPayload::choices(vec![0, 2, 3]))),
+            ("d", Some(AnswerPayload::choice(0))),
+        ]);
+        assert_eq!(judge_answers(&kind, &given), correct(&["a"]));
+
+        let kind = QuestionKind::multiSelect {
+            answers: options(4),
+            correct_answers: vec![3],
+        };
+        let given = answers(&[("a", Some(AnswerPayload::choice(3)))]);
+        assert_eq!(judge_answers(&kind, &given), correct(&["a"]));
+    }
+
+    #[test]
+    fn numeric_closest_answers_win() {
+        let kind = QuestionKind::numeric {
+            correct_value: 100.0,
+        };
+        let given = answers(&[
+            ("a", Some(AnswerPayload::number(90.0))),
+            ("b", Some(AnswerPayload::number(110.0))),
+            ("c", Some(AnswerPayload::choice(150))),
+            ("d", Some(AnswerPayload::text("100".to_string()))),
+            ("e", None),
+        ]);
+        assert_eq!(judge_answers(&kind, &given), correct(&["a", "b"]));
+
+        let nobody = answers(&[("a", None)]);
+        assert!(judge_answers(&kind, &nobody).is_empty());
+    }
+
+    #[test]
+    fn free_text_ignores_case_and_spacing() {
+        let kind = QuestionKind::freeText {
+            accepted_answers: vec!["New York".to_string(), "NYC".to_string()],
+        };
+        let given = answers(&[
+            ("a", Some(AnswerPayload::text("  new   york ".to_string()))),
+            ("b", Some(AnswerPayload::text("nyc".to_string()))),
+            ("c", Some(AnswerPayload::text("newyork".to_string()))),
+            ("d", Some(AnswerPayload::choice(0))),
+        ]);
+        assert_eq!(judge_answers(&kind, &given), correct(&["a", "b"]));
+    }
+
+    fn ranks(scores: &[(&str, i32)], correct_counts: &[(&str, i32)]) -> Vec<(i32, String, i32)> {
+        let scores = scores
+            .iter()
+            .map(|(user_id, score)| (user_id.to_string(), *score))
+            .collect();
+        let correct_counts = correct_counts
+            .iter()
+            .map(|(user_id, count)| (user_id.to_string(), *count))
+            .collect();
+        rank_standings(&scores, &correct_counts)
+            .into_iter()
+            .map(|standing| (standing.rank, standing.userId, standing.correctAnswers))
+            .collect()
+    }
+
+    #[test]
+    fn ties_share_a_rank_and_skip_the_next() {
+        let standings = ranks(
+            &[("c", 300), ("a", 500), ("b", 500), ("d", 100)],
+            &[("a", 5), ("b", 4), ("c", 3)],
+        );
+        assert_eq!(
+            standings,
+            vec![
+                (1, "a".to_string(), 5),
+                (1, "b".to_string(), 4),
+                (3, "c".to_string(), 3),
+                (4, "d".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn everyone_tied_is_first() {
+        let standings = ranks(&[("b", 0), ("a", 0), ("c", 0)], &[]);
+        let ranks: Vec<i32> = standings.iter().map(|standing| standing.0).collect();
+        assert_eq!(ranks, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn no_players_no_standings() {
+        assert!(rank_standings(&HashMap::new(), &HashMap::new()).is_empty());
+    }
 }
@@ -0,0 +1,85 @@
+use crate::{
+    http::HttpRequest, metrics::render_metrics, models::lobby::RoomInfo,
+    shutdown::is_shutting_down, state::AppState,
+};
+use log::{info, warn};
+use std::net::SocketAddr;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+// Plain HTTP requests on the WebSocket listener, for load balancer probes and dashboards
+pub async fn handle_http_request<S>(
+    request: HttpRequest,
+    mut stream: S,
+    addr: SocketAddr,
+    state: AppState,
+) where
+    S: AsyncWrite + Unpin,
+{
+    info!("HTTP {} {} from: {}", &request.method, &request.path, &addr);
+
+    let (status, content_type, body) = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/healthz") => ("200 OK", "text/plain", "ok".to_string()),
+        ("GET", "/readyz") => match is_shutting_down() {
+            false => ("200 OK", "text/plain", "ready".to_string()),
+            true => (
+                "503 Service Unavailable",
+                "text/plain",
+                "shutting down".to_string(),
+            ),
+        },
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", render_metrics(state)),
+        ("GET", "/rooms") => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string(&get_room_info(state)).unwrap(),
+        ),
+        (_, "/healthz") | (_, "/readyz") | (_, "/rooms") | (_, "/metrics") => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed".to_string(),
+        ),
+        _ => ("404 Not Found", "text/plain", "not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    match stream.write_all(response.as_bytes()).await {
+        Ok(_) => (),
+        Err(error) => warn!("Could not write HTTP response to {}: {}", &addr, error),
+    }
+    let _ = stream.shutdown().await;
+}
+
+// Public rooms only, private rooms and their codes are never listed
+fn get_room_info(state: AppState) -> Vec<RoomInfo> {
+    let peers = state.peers.lock().unwrap();
+    let users = state.users.lock().unwrap();
+    let games = state.games.lock().unwrap();
+
+    let mut rooms: Vec<RoomInfo> = state
+        .rooms
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|room| room.settings.public)
+        .map(|room| RoomInfo {
+            id: room.id.clone(),
+            code: room.code.clone(),
+            currentPlayers: room.current_players,
+            connectedPlayers: users
+                .iter()
+                .filter(|user| user.roomId == room.id && peers.contains_key(&user.id))
+                .count(),
+            maxPlayers: room.settings.maxPlayers,
+            gameRunning: games.contains_key(&room.id),
+        })
+        .collect();
+    rooms.sort_by(|a, b| a.id.cmp(&b.id));
+
+    rooms
+}
@@ -1,12 +1,13 @@
 use crate::{
     helpers::{edit_list_element, get_list_element, get_room_user_list},
-    models::{
-        communication::Response,
-        lobby::{Room, User},
-    },
+    invites::{revoke_invites, Invite},
+    metrics::metrics,
+    models::{communication::Response, lobby::Room},
     server_messages::broadcast_message_room_all,
+    sessions::revoke_user_sessions,
+    state::AppState,
 };
-use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use futures_channel::mpsc::UnboundedReceiver;
 use futures_timer::Delay;
 use futures_util::{
     future::{self},
@@ -19,15 +20,11 @@ use std::{
     sync::{Arc, Mutex},
     time::Duration,
 };
-use tungstenite::Message;
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<HashMap<String, Tx>>>;
-type UserList = Arc<Mutex<Vec<User>>>;
 type RoomList = Arc<Mutex<Vec<Room>>>;
-type Lists = (PeerMap, UserList, RoomList);
+type InviteList = Arc<Mutex<HashMap<String, Invite>>>;
 
-pub async fn handle_room_timeout(room_id: String, room_list: RoomList) {
+pub async fn handle_room_timeout(room_id: String, room_list: RoomList, invites: InviteList) {
     Delay::new(Duration::from_secs(10)).await;
 
     let room_info = match get_list_element(&room_id, room_list.clone()) {
@@ -49,6 +46,7 @@ pub async fn handle_room_timeout(room_id: String, room_list: RoomList) {
         match index {
             Some(index) => {
                 room_list.lock().unwrap().remove(index);
+                revoke_invites(&room_id, &None, invites);
             }
             None => println!("No index found for room!"),
         }
@@ -60,14 +58,12 @@ pub async fn handle_room_timeout(room_id: String, room_list: RoomList) {
 pub async fn handle_user_timeout(
     user_id: String,
     room_id: String,
-    lists: Lists,
+    state: AppState,
     rx: UnboundedReceiver<bool>,
 ) {
     let timer = Delay::new(Duration::from_secs(10));
 
-    let receive_future = rx
-        .take_while(|msg| future::ready(msg.clone()))
-        .into_future();
+    let receive_future = rx.take_while(|msg| future::ready(*msg)).into_future();
 
     pin_mut!(timer, receive_future);
     let select = future::select(timer, receive_future).await;
@@ -76,13 +72,13 @@ pub async fn handle_user_timeout(
         future::Either::Left(_) => {
             println!("Timer finished first!");
 
-            let user_info = get_list_element(&user_id, lists.1.clone());
-            if !lists.0.lock().unwrap().contains_key(&user_id) {
+            let user_info = get_list_element(&user_id, state.users.clone());
+            if !state.peers.lock().unwrap().contains_key(&user_id) {
                 println!("Removing user: {}", &user_id);
                 info!("Removing user: {}", &user_id);
 
-                let index = lists
-                    .1
+                let index = state
+                    .users
                     .lock()
                     .unwrap()
                     .iter()
@@ -90,8 +86,10 @@ pub async fn handle_user_timeout(
 
                 match index {
                     Some(index) => {
-                        lists.1.lock().unwrap().remove(index);
-                        edit_list_element(&room_id, lists.2.clone(), |room| {
+                        state.users.lock().unwrap().remove(index);
+                        metrics().record_timeout_removal();
+                        revoke_user_sessions(&user_id, state.sessions.clone());
+                        edit_list_element(&room_id, state.rooms.clone(), |room| {
                             room.current_players -= 1;
                         })
                         .unwrap();
@@ -101,15 +99,19 @@ pub async fn handle_user_timeout(
                                 if user.isHost {
                                     println!("Host disconnected!");
                                     let room_users =
-                                        get_room_user_list(&user.roomId, lists.1.clone());
+                                        get_room_user_list(&user.roomId, state.users.clone());
                                     let random_user = room_users.choose(&mut rand::thread_rng());
 
                                     match random_user {
                                         Some(user) => {
                                             println!("Making {} host", &user.name);
-                                            edit_list_element(&user.id, lists.1.clone(), |user| {
-                                                user.isHost = true;
-                                            })
+                                            edit_list_element(
+                                                &user.id,
+                                                state.users.clone(),
+                                                |user| {
+                                                    user.isHost = true;
+                                                },
+                                            )
                                             .unwrap();
                                         }
                                         None => {
@@ -123,30 +125,35 @@ pub async fn handle_user_timeout(
                             None => println!("No user info found"),
                         }
 
-                        let user_list = get_room_user_list(&room_id, lists.1.clone());
+                        state.notify_room_list_changed();
+
+                        let user_list = get_room_user_list(&room_id, state.users.clone());
                         let update_user_list_response = Response::updateUserList {
                             userList: user_list.clone(),
                         };
                         broadcast_message_room_all(
                             update_user_list_response,
-                            lists.0.clone(),
+                            state.peers.clone(),
                             &user_list,
                         );
 
-                        let room_info = get_list_element(&room_id, lists.2.clone()).unwrap();
+                        let room_info = get_list_element(&room_id, state.rooms.clone()).unwrap();
                         if room_info.current_players <= 0 {
                             println!("Removing room: {}", &room_id);
                             info!("Removing room: {}", &room_id);
 
-                            let index = lists
-                                .2
+                            let index = state
+                                .rooms
                                 .lock()
                                 .unwrap()
                                 .iter()
                                 .position(|room| room.id == room_id);
                             match index {
                                 Some(index) => {
-                                    lists.2.lock().unwrap().remove(index);
+                                    state.rooms.lock().unwrap().remove(index);
+                                    // Invites to the room would otherwise never be removed
+                                    revoke_invites(&room_id, &None, state.invites.clone());
+                                    state.notify_room_list_changed();
                                 }
                                 None => println!("No index found for room!"),
                             }
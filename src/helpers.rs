@@ -3,79 +3,96 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use crate::models::{
-    communication::Command,
-    game::GameCommand,
-    lobby::{HasId, Room, User},
+use crate::{
+    errors::ServerError,
+    models::{
+        communication::Command,
+        game::GameCommand,
+        lobby::{HasId, User},
+    },
+    state::AppState,
 };
 use futures_channel::mpsc::UnboundedSender;
+use log::warn;
 use tungstenite::Message;
 
 type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<HashMap<String, Tx>>>;
+type GameList = Arc<Mutex<HashMap<String, Tx>>>;
 type UserList = Arc<Mutex<Vec<User>>>;
-type RoomList = Arc<Mutex<Vec<Room>>>;
-type Lists = (PeerMap, UserList, RoomList);
 
-pub fn parse_command(msg: &Message) -> Result<Command, String> {
+pub fn parse_command(msg: &Message) -> Result<Command, ServerError> {
     let unauthorized_command = match serde_json::from_str(&msg.to_string()) {
         Ok(command) => return Ok(Command::UnauthorizedCommand(command)),
         Err(error) => error.to_string(),
     };
     match serde_json::from_str(&msg.to_string()) {
-        Ok(command) => return Ok(Command::CommandTokenPair(command)),
-        Err(error) => {
-            return Err(format!(
-                "Cannot parse authorized command: {}; Cannot parse unauthorized command: {}",
-                error.to_string(),
-                unauthorized_command
-            ))
-        }
-    };
+        Ok(command) => Ok(Command::CommandTokenPair(command)),
+        Err(error) => Err(ServerError::InvalidCommand(format!(
+            "Cannot parse authorized command: {}; Cannot parse unauthorized command: {}",
+            error, unauthorized_command
+        ))),
+    }
+}
+
+// Best effort for messages that are not a valid command, so the error can still be matched
+pub fn parse_request_id(msg: &Message) -> Option<String> {
+    match serde_json::from_str::<serde_json::Value>(&msg.to_string()) {
+        Ok(value) => value
+            .get("requestId")
+            .and_then(|request_id| request_id.as_str())
+            .map(|request_id| request_id.to_string()),
+        Err(_) => None,
+    }
 }
 
-pub fn parse_game_command(msg: &Message) -> Result<(String, i32), String> {
+pub fn parse_game_command(msg: &Message) -> Result<GameCommand, ServerError> {
     let parsed_msg: Result<GameCommand, serde_json::Error> = serde_json::from_str(&msg.to_string());
     match parsed_msg {
-        Ok(command) => {
-            return Ok((command.user_id, command.answer));
+        Ok(command) => Ok(command),
+        Err(error) => Err(ServerError::InvalidCommand(error.to_string())),
+    }
+}
+
+pub fn send_game_command(command: &GameCommand, room_id: &String, game_list: GameList) {
+    if let Some(tx) = game_list.lock().unwrap().get(room_id) {
+        match tx.unbounded_send(Message::Text(serde_json::to_string(command).unwrap())) {
+            Ok(_) => (),
+            Err(error) => warn!("Could not send game command: {}", error),
         }
-        Err(error) => return Err(error.to_string()),
     }
 }
 
 pub fn connect_user_to_room(
     room_id: &String,
     user_id: &String,
-    lists: Lists,
-) -> Result<Vec<User>, String> {
-    match edit_list_element(room_id, lists.2.clone(), |room| {
+    state: &AppState,
+) -> Result<Vec<User>, ServerError> {
+    match edit_list_element(room_id, state.rooms.clone(), |room| {
         room.current_players += 1;
     }) {
         Ok(_) => (),
         Err(error) => {
-            return Err(format!("connect_user_to_room: {}", error));
+            return Err(error);
         }
     }
 
-    let room_info = get_list_element(room_id, lists.2.clone());
+    let room_info = get_list_element(room_id, state.rooms.clone());
     match room_info {
-        Some(room) => {
-            if room.current_players == 1 {
-                match edit_list_element(user_id, lists.1.clone(), |user| {
-                    user.isHost = true;
-                }) {
-                    Ok(_) => (),
-                    Err(error) => {
-                        return Err(format!("connect_user_to_room: {}", error));
-                    }
+        Some(room) if room.current_players == 1 => {
+            match edit_list_element(user_id, state.users.clone(), |user| {
+                user.isHost = true;
+            }) {
+                Ok(_) => (),
+                Err(error) => {
+                    return Err(error);
                 }
             }
         }
-        None => (),
+        _ => (),
     }
+    state.notify_room_list_changed();
 
-    Ok(get_room_user_list(room_id, lists.1.clone()))
+    Ok(get_room_user_list(room_id, state.users.clone()))
 }
 
 pub fn get_room_user_list(room_id: &String, user_list: UserList) -> Vec<User> {
@@ -83,37 +100,34 @@ pub fn get_room_user_list(room_id: &String, user_list: UserList) -> Vec<User> {
         .lock()
         .unwrap()
         .iter()
-        .cloned()
         .filter(|user| &user.roomId == room_id)
+        .cloned()
         .collect();
 
-    return users;
+    users
 }
 
 pub fn get_list_element<T: Clone + HasId>(id: &String, list: Arc<Mutex<Vec<T>>>) -> Option<T> {
-    return match list
+    return list
         .lock()
         .unwrap()
         .iter()
         .find(|element| &element.get_id() == id)
-    {
-        Some(element) => Some(element.clone()),
-        None => None,
-    };
+        .cloned();
 }
 
 pub fn edit_list_element<F, T: HasId>(
     id: &String,
     list: Arc<Mutex<Vec<T>>>,
     mut function: F,
-) -> Result<(), String>
+) -> Result<(), ServerError>
 where
     F: FnMut(&mut T),
 {
     let mut elements = list.lock().unwrap();
     let target_element = match elements.iter_mut().find(|element| &element.get_id() == id) {
         Some(element) => element,
-        None => return Err("edit_list_element: Element does not exist in list".to_string()),
+        None => return Err(ServerError::ElementNotFound(id.clone())),
     };
 
     function(target_element);
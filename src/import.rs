@@ -0,0 +1,502 @@
+use std::fmt;
+
+use crate::{
+    models::game::{Answer, Pack, PackMetadata, Question, QuestionKind, Scoring},
+    validation::validate_pack,
+};
+
+#[derive(Debug)]
+pub struct ImportError {
+    // 1-based record number, the header is record 1
+    pub record: usize,
+    // 1-based line the record starts on, quoted fields can span several lines
+    pub line: usize,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "record {} (line {})", self.record, self.line)?;
+        match self.column {
+            Some(column) => write!(f, ", column {}: {}", column, self.message),
+            None => write!(f, ": {}", self.message),
+        }
+    }
+}
+
+// One record of delimited text
+pub struct Record {
+    pub number: usize,
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+// Column positions found in the header row
+struct Columns {
+    question: usize,
+    answers: Vec<usize>,
+    correct_answer: usize,
+    duration: usize,
+    question_type: Option<usize>,
+}
+
+// Builds a pack from CSV/TSV text. The header row names the columns: "question",
+// "answer 1".."answer N", "correct_answer", "duration_sec" and an optional "type"
+pub fn import_pack(data: &str, delimiter: char, name: &str) -> Result<Pack, Vec<ImportError>> {
+    let rows = parse_delimited(data, delimiter)?;
+    let header = match rows.first() {
+        Some(header) => header,
+        None => {
+            return Err(vec![ImportError {
+                record: 1,
+                line: 1,
+                column: None,
+                message: "file is empty".to_string(),
+            }])
+        }
+    };
+    let columns = find_columns(header)?;
+
+    let mut errors = Vec::new();
+    let mut questions = Vec::new();
+    let mut question_records = Vec::new();
+    for record in rows.iter().skip(1) {
+        if record.fields.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        match parse_question(record, &columns) {
+            Ok(question) => {
+                questions.push(question);
+                question_records.push(record);
+            }
+            Err(mut row_errors) => errors.append(&mut row_errors),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let pack = Pack {
+        name: name.to_string(),
+        metadata: PackMetadata::default(),
+        questions,
+        scoring: Scoring::default(),
+        end_when_all_answered: false,
+    };
+
+    // Report pack problems against the spreadsheet records they came from
+    let problems = validate_pack(&pack);
+    if !problems.is_empty() {
+        return Err(problems
+            .into_iter()
+            .map(|problem| {
+                let (record, line) = match problem.question_index {
+                    Some(index) => (question_records[index].number, question_records[index].line),
+                    None => (header.number, header.line),
+                };
+                ImportError {
+                    record,
+                    line,
+                    column: None,
+                    message: problem.message,
+                }
+            })
+            .collect());
+    }
+
+    Ok(pack)
+}
+
+fn find_columns(header: &Record) -> Result<Columns, Vec<ImportError>> {
+    let mut question = None;
+    let mut answers = Vec::new();
+    let mut correct_answer = None;
+    let mut duration = None;
+    let mut question_type = None;
+
+    for (index, title) in header.fields.iter().enumerate() {
+        let title: String = title
+            .trim()
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect();
+        match title.as_str() {
+            "question" | "text" => question = Some(index),
+            "correct" | "correctanswer" => correct_answer = Some(index),
+            "duration" | "durationsec" => duration = Some(index),
+            "type" => question_type = Some(index),
+            _ if title.starts_with("answer") => answers.push(index),
+            _ => (),
+        }
+    }
+
+    let mut errors = Vec::new();
+    let mut require = |column: Option<usize>, title: &str| {
+        if column.is_none() {
+            errors.push(ImportError {
+                record: header.number,
+                line: header.line,
+                column: None,
+                message: format!("missing \"{}\" column", title),
+            });
+        }
+        column.unwrap_or(0)
+    };
+    let columns = Columns {
+        question: require(question, "question"),
+        correct_answer: require(correct_answer, "correct_answer"),
+        duration: require(duration, "duration_sec"),
+        answers,
+        question_type,
+    };
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(columns)
+}
+
+fn parse_question(record: &Record, columns: &Columns) -> Result<Question, Vec<ImportError>> {
+    let field = |column: usize| {
+        record
+            .fields
+            .get(column)
+            .map(|field| field.trim())
+            .unwrap_or("")
+    };
+    let error = |column: usize, message: String| ImportError {
+        record: record.number,
+        line: record.line,
+        column: Some(column + 1),
+        message,
+    };
+    let mut errors = Vec::new();
+
+    let duration_sec = match field(columns.duration).parse::<i32>() {
+        Ok(duration) => duration,
+        Err(_) => {
+            errors.push(error(
+                columns.duration,
+                format!(
+                    "duration \"{}\" is not a whole number",
+                    field(columns.duration)
+                ),
+            ));
+            0
+        }
+    };
+
+    // Answer columns are numbered by position, empty cells are left out
+    let answers: Vec<Answer> = columns
+        .answers
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| !field(**column).is_empty())
+        .map(|(index, column)| Answer {
+            number: index as i32 + 1,
+            text: field(*column).to_string(),
+        })
+        .collect();
+
+    let correct_column = columns.correct_answer;
+    let correct = field(correct_column);
+    let question_type = match columns.question_type {
+        Some(column) if !field(column).is_empty() => field(column),
+        _ => "singleChoice",
+    };
+    let kind = match question_type {
+        "singleChoice" => match correct.parse::<i32>() {
+            Ok(correct_answer) => Some(QuestionKind::singleChoice {
+                answers,
+                correct_answer,
+            }),
+            Err(_) => {
+                errors.push(error(
+                    correct_column,
+                    format!("correct answer \"{}\" is not an answer number", correct),
+                ));
+                None
+            }
+        },
+        "trueFalse" => match correct.to_lowercase().parse::<bool>() {
+            Ok(correct_answer) => Some(QuestionKind::trueFalse { correct_answer }),
+            Err(_) => {
+                errors.push(error(
+                    correct_column,
+                    format!("correct answer \"{}\" is not true or false", correct),
+                ));
+                None
+            }
+        },
+        "multiSelect" => {
+            let numbers: Result<Vec<i32>, _> = correct
+                .split([',', ';'])
+                .map(|number| number.trim().parse::<i32>())
+                .collect();
+            match numbers {
+                Ok(correct_answers) => Some(QuestionKind::multiSelect {
+                    answers,
+                    correct_answers,
+                }),
+                Err(_) => {
+                    errors.push(error(
+                        correct_column,
+                        format!("correct answers \"{}\" are not answer numbers", correct),
+                    ));
+                    None
+                }
+            }
+        }
+        "numeric" => match correct.parse::<f64>() {
+            Ok(correct_value) => Some(QuestionKind::numeric { correct_value }),
+            Err(_) => {
+                errors.push(error(
+                    correct_column,
+                    format!("correct answer \"{}\" is not a number", correct),
+                ));
+                None
+            }
+        },
+        // Accepted spellings go in the answer columns, the correct column is one more spelling
+        "freeText" => {
+            let mut accepted_answers: Vec<String> =
+                answers.into_iter().map(|answer| answer.text).collect();
+            if !correct.is_empty() {
+                accepted_answers.insert(0, correct.to_string());
+            }
+            Some(QuestionKind::freeText { accepted_answers })
+        }
+        other => {
+            errors.push(error(
+                columns.question_type.unwrap_or(0),
+                format!("unknown question type \"{}\"", other),
+            ));
+            None
+        }
+    };
+
+    match kind {
+        Some(kind) if errors.is_empty() => Ok(Question {
+            text: field(columns.question).to_string(),
+            duration_sec,
+            kind,
+        }),
+        _ => Err(errors),
+    }
+}
+
+// Splits delimited text into records of fields, honouring double-quoted fields that may
+// span lines. Each record knows its number and the 1-based line it starts on
+pub fn parse_delimited(data: &str, delimiter: char) -> Result<Vec<Record>, Vec<ImportError>> {
+    let mut rows = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut row_start = 1;
+
+    let mut chars = data.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' | '\r' => {
+                fields.push(std::mem::take(&mut field));
+                rows.push(Record {
+                    number: rows.len() + 1,
+                    line: row_start,
+                    fields: std::mem::take(&mut fields),
+                });
+                line += 1;
+                row_start = line;
+            }
+            _ if c == delimiter => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(vec![ImportError {
+            record: rows.len() + 1,
+            line: row_start,
+            column: Some(fields.len() + 1),
+            message: "quoted field is never closed".to_string(),
+        }]);
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        rows.push(Record {
+            number: rows.len() + 1,
+            line: row_start,
+            fields,
+        });
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(records: &[Record]) -> Vec<(usize, usize, Vec<&str>)> {
+        records
+            .iter()
+            .map(|record| {
+                (
+                    record.number,
+                    record.line,
+                    record.fields.iter().map(|field| field.as_str()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn errors(result: Result<Pack, Vec<ImportError>>) -> Vec<String> {
+        match result {
+            Ok(_) => panic!("expected import errors"),
+            Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn splits_records_and_fields() {
+        let records = parse_delimited("\u{feff}a,b,c\r\n1,,3\n", ',').unwrap();
+        assert_eq!(
+            fields(&records),
+            vec![(1, 1, vec!["a", "b", "c"]), (2, 2, vec!["1", "", "3"])]
+        );
+
+        let records = parse_delimited("a\tb\n1\t2", '\t').unwrap();
+        assert_eq!(
+            fields(&records),
+            vec![(1, 1, vec!["a", "b"]), (2, 2, vec!["1", "2"])]
+        );
+    }
+
+    #[test]
+    fn quoted_fields_keep_delimiters_quotes_and_newlines() {
+        let records =
+            parse_delimited("\"a,b\",\"say \"\"hi\"\"\"\n\"two\nlines\",x\nlast,y", ',').unwrap();
+        assert_eq!(
+            fields(&records),
+            vec![
+                (1, 1, vec!["a,b", "say \"hi\""]),
+                (2, 2, vec!["two\nlines", "x"]),
+                (3, 4, vec!["last", "y"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unclosed_quotes() {
+        let errors = match parse_delimited("a,b\n\"open,x\n", ',') {
+            Ok(_) => panic!("expected an error"),
+            Err(errors) => errors,
+        };
+        assert_eq!(
+            errors[0].to_string(),
+            "record 2 (line 2), column 1: quoted field is never closed"
+        );
+    }
+
+    #[test]
+    fn imports_every_question_type() {
+        let data = "question,answer 1,answer 2,answer 3,correct_answer,duration_sec,type
+Capital of France?,Paris,Rome,,1,20,
+Sky is blue?,,,,true,10,trueFalse
+Primes?,2,4,5,\"1,3\",15,multiSelect
+Pi?,,,,3.14,10,numeric
+Largest ocean?,Pacific Ocean,,,Pacific,10,freeText
+";
+        let pack = import_pack(data, ',', "imported").unwrap();
+        assert_eq!(pack.name, "imported");
+        let types: Vec<String> = pack
+            .questions
+            .iter()
+            .map(|question| question.kind.name())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                "singleChoice",
+                "trueFalse",
+                "multiSelect",
+                "numeric",
+                "freeText"
+            ]
+        );
+        match &pack.questions[0].kind {
+            QuestionKind::singleChoice {
+                answers,
+                correct_answer,
+            } => {
+                assert_eq!(answers.len(), 2);
+                assert_eq!(*correct_answer, 1);
+            }
+            _ => panic!("expected a single choice question"),
+        }
+        match &pack.questions[4].kind {
+            QuestionKind::freeText { accepted_answers } => {
+                assert_eq!(accepted_answers, &vec!["Pacific", "Pacific Ocean"])
+            }
+            _ => panic!("expected a free text question"),
+        }
+    }
+
+    #[test]
+    fn reports_missing_columns() {
+        assert_eq!(
+            errors(import_pack("question,answer 1\nq,a\n", ',', "pack")),
+            vec![
+                "record 1 (line 1): missing \"correct_answer\" column",
+                "record 1 (line 1): missing \"duration_sec\" column",
+            ]
+        );
+        assert_eq!(
+            errors(import_pack("", ',', "pack")),
+            vec!["record 1 (line 1): file is empty"]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_record_and_its_starting_line() {
+        let data = "question,answer 1,answer 2,correct,duration
+\"Two
+line question\",a,b,1,soon
+q,a,b,3,10
+";
+        assert_eq!(
+            errors(import_pack(data, ',', "pack")),
+            vec!["record 2 (line 2), column 5: duration \"soon\" is not a whole number"]
+        );
+
+        let data = "question,answer 1,answer 2,correct,duration
+\"Two
+line question\",a,b,1,10
+q,a,b,3,10
+";
+        assert_eq!(
+            errors(import_pack(data, ',', "pack")),
+            vec!["record 3 (line 4): correct_answer 3 is not among the answers"]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use chrono::Utc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+use crate::{
+    errors::ServerError,
+    jwtoken::{decode_invite_token, generate_invite_token, TokenError},
+};
+
+type InviteList = Arc<Mutex<HashMap<String, Invite>>>;
+
+pub const DEFAULT_INVITE_VALID_SEC: i64 = 24 * 60 * 60;
+pub const MAX_INVITE_VALID_SEC: i64 = 7 * 24 * 60 * 60;
+
+// Outstanding invite, removed once used or revoked so its token stops working
+#[derive(Clone)]
+pub struct Invite {
+    pub id: String,
+    pub room_id: String,
+    pub expires_at: i64,
+}
+
+// Creates an invite to the room and returns it with its signed token
+pub fn issue_invite_token(
+    room_id: &str,
+    valid_for_sec: i64,
+    invites: InviteList,
+) -> Result<(Invite, String), TokenError> {
+    let invite = Invite {
+        id: Uuid::new_v4().to_string(),
+        room_id: room_id.to_string(),
+        expires_at: Utc::now().timestamp() + valid_for_sec.clamp(1, MAX_INVITE_VALID_SEC),
+    };
+    let token = generate_invite_token(&invite)?;
+
+    let mut invites = invites.lock().unwrap();
+    let now = Utc::now().timestamp();
+    invites.retain(|_, invite| invite.expires_at > now);
+    invites.insert(invite.id.clone(), invite.clone());
+
+    Ok((invite, token))
+}
+
+// Checks the token is a live invite to the room without using it up, so a join that
+// fails later on can be retried with the same invite
+pub fn check_invite(
+    token: &str,
+    room_id: &String,
+    invites: InviteList,
+) -> Result<Invite, ServerError> {
+    let claims = match decode_invite_token(token) {
+        Ok(token_data) => token_data.claims,
+        Err(error) => return Err(ServerError::InvalidInvite(error.to_string())),
+    };
+    if &claims.room != room_id {
+        return Err(ServerError::InvalidInvite(
+            "invite is for another room".to_string(),
+        ));
+    }
+
+    let mut invites = invites.lock().unwrap();
+    match invites.get(&claims.iid) {
+        Some(invite) if invite.expires_at <= Utc::now().timestamp() => {
+            invites.remove(&claims.iid);
+            Err(ServerError::InvalidInvite("invite expired".to_string()))
+        }
+        Some(invite) => Ok(invite.clone()),
+        None => Err(ServerError::InvalidInvite(
+            "invite was used or revoked".to_string(),
+        )),
+    }
+}
+
+// Uses up a checked invite once its player has joined, None if it is already gone
+pub fn redeem_invite(invite_id: &String, invites: InviteList) -> Option<Invite> {
+    invites.lock().unwrap().remove(invite_id)
+}
+
+// Revokes one invite of the room, or all of them without an id. Returns the revoked ids
+pub fn revoke_invites(
+    room_id: &String,
+    invite_id: &Option<String>,
+    invites: InviteList,
+) -> Vec<String> {
+    let mut revoked = Vec::new();
+    invites.lock().unwrap().retain(|id, invite| {
+        let matches = &invite.room_id == room_id
+            && match invite_id {
+                Some(invite_id) => id == invite_id,
+                None => true,
+            };
+        if matches {
+            revoked.push(id.clone());
+        }
+        !matches
+    });
+    revoked.sort();
+    revoked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwtoken::init_test_keys;
+
+    fn invite_list() -> InviteList {
+        InviteList::new(Mutex::new(HashMap::new()))
+    }
+
+    fn invite_error(result: Result<Invite, ServerError>) -> String {
+        match result {
+            Err(ServerError::InvalidInvite(message)) => message,
+            Err(_) => panic!("expected InvalidInvite"),
+            Ok(_) => panic!("expected the invite to be rejected"),
+        }
+    }
+
+    #[test]
+    fn checked_invites_work_until_redeemed() {
+        init_test_keys();
+        let invites = invite_list();
+        let (invite, token) = issue_invite_token("room", 60, invites.clone()).unwrap();
+        let room_id = "room".to_string();
+
+        // Checking alone doesn't use the invite up
+        assert_eq!(
+            check_invite(&token, &room_id, invites.clone()).unwrap().id,
+            invite.id
+        );
+        assert!(check_invite(&token, &room_id, invites.clone()).is_ok());
+
+        assert!(redeem_invite(&invite.id, invites.clone()).is_some());
+        assert!(redeem_invite(&invite.id, invites.clone()).is_none());
+        assert_eq!(
+            invite_error(check_invite(&token, &room_id, invites)),
+            "invite was used or revoked"
+        );
+    }
+
+    #[test]
+    fn rejects_invites_to_other_rooms() {
+        init_test_keys();
+        let invites = invite_list();
+        let (_, token) = issue_invite_token("room", 60, invites.clone()).unwrap();
+
+        assert_eq!(
+            invite_error(check_invite(&token, &"other".to_string(), invites)),
+            "invite is for another room"
+        );
+    }
+
+    #[test]
+    fn rejects_and_removes_expired_invites() {
+        init_test_keys();
+        let invites = invite_list();
+        let (invite, token) = issue_invite_token("room", 60, invites.clone()).unwrap();
+        invites
+            .lock()
+            .unwrap()
+            .get_mut(&invite.id)
+            .unwrap()
+            .expires_at = 0;
+
+        assert_eq!(
+            invite_error(check_invite(&token, &"room".to_string(), invites.clone())),
+            "invite expired"
+        );
+        assert!(invites.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_tokens_that_are_not_invites() {
+        init_test_keys();
+        invite_error(check_invite(
+            "not a token",
+            &"room".to_string(),
+            invite_list(),
+        ));
+    }
+
+    #[test]
+    fn revokes_one_or_all_invites_of_a_room() {
+        init_test_keys();
+        let invites = invite_list();
+        let (first, _) = issue_invite_token("room", 60, invites.clone()).unwrap();
+        let (second, _) = issue_invite_token("room", 60, invites.clone()).unwrap();
+        let (other, _) = issue_invite_token("other", 60, invites.clone()).unwrap();
+        let room_id = "room".to_string();
+
+        // Ids of other rooms are left alone
+        assert!(revoke_invites(&room_id, &Some(other.id.clone()), invites.clone()).is_empty());
+        assert_eq!(
+            revoke_invites(&room_id, &Some(first.id.clone()), invites.clone()),
+            vec![first.id]
+        );
+        assert_eq!(
+            revoke_invites(&room_id, &None, invites.clone()),
+            vec![second.id]
+        );
+        assert_eq!(
+            invites.lock().unwrap().keys().collect::<Vec<_>>(),
+            vec![&other.id]
+        );
+    }
+}
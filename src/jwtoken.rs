@@ -1,54 +1,354 @@
-use chrono::{Days, Utc};
 use jsonwebtoken::{
-    decode, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData,
+    Validation,
 };
-use serde::{Deserialize, Serialize};
+use log::warn;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, env, fmt, fs, sync::OnceLock};
 
-use crate::models::lobby::User;
+use crate::{
+    config::{JwtConfig, JwtKeyConfig},
+    invites::Invite,
+    sessions::Session,
+};
 
-pub fn generate_token(user: &User) -> Result<String, jsonwebtoken::errors::Error> {
-    let expiration = Utc::now()
-        .checked_add_days(Days::new(1))
-        .expect("Timestamp invalid")
-        .timestamp();
+static KEYS: OnceLock<KeyStore> = OnceLock::new();
 
-    let new_claims = Claims {
-        id: user.id.clone(),
-        roomId: user.roomId.clone(),
-        name: user.name.clone(),
-        avatarPath: user.avatarPath.clone(),
-        isHost: user.isHost.clone(),
-        userColor: user.userColor.clone(),
-        exp: expiration as usize,
+// Set as the typ claim, so a token can't be used as the other kind
+const SESSION_TOKEN_TYPE: &str = "session";
+const INVITE_TOKEN_TYPE: &str = "invite";
+
+struct KeyStore {
+    signing_kid: String,
+    signing_algorithm: Algorithm,
+    signing_key: EncodingKey,
+    // Every key that is still accepted, so tokens signed before a rotation stay valid
+    verifying_keys: HashMap<String, (Algorithm, DecodingKey)>,
+}
+
+#[derive(Debug)]
+pub enum TokenError {
+    // The key config is incomplete or names an unsupported algorithm
+    InvalidKeyConfig(String),
+    KeyFile(String, std::io::Error),
+    UnknownKeyId(String),
+    WrongType(String),
+    Jwt(jsonwebtoken::errors::Error),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::InvalidKeyConfig(message) => write!(f, "Invalid key config: {}", message),
+            TokenError::KeyFile(path, error) => {
+                write!(f, "Cannot read key file {}: {}", path, error)
+            }
+            TokenError::UnknownKeyId(kid) => write!(f, "Unknown token key id: {}", kid),
+            TokenError::WrongType(typ) => write!(f, "Wrong token type: {}", typ),
+            TokenError::Jwt(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+impl From<jsonwebtoken::errors::Error> for TokenError {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        TokenError::Jwt(error)
+    }
+}
+
+// Loads the signing key and every verification key listed in the config
+pub fn init_keys(config: &JwtConfig) -> Result<(), TokenError> {
+    let mut signing = None;
+    let mut verifying_keys = HashMap::new();
+
+    for key_config in &config.keys {
+        let (encoding_key, decoding_key) = load_key(key_config)?;
+        verifying_keys.insert(key_config.kid.clone(), (key_config.algorithm, decoding_key));
+        if key_config.kid == config.signing_kid {
+            match encoding_key {
+                Some(encoding_key) => signing = Some((key_config.algorithm, encoding_key)),
+                None => {
+                    return Err(TokenError::InvalidKeyConfig(format!(
+                        "signing key {} has no private key",
+                        key_config.kid
+                    )))
+                }
+            }
+        }
+    }
+
+    let (signing_algorithm, signing_key) = match signing {
+        Some(signing) => signing,
+        None => {
+            return Err(TokenError::InvalidKeyConfig(format!(
+                "signing key {} is not among the keys",
+                config.signing_kid
+            )))
+        }
     };
-    let token = encode(
-        &Header::default(),
-        &new_claims,
-        &EncodingKey::from_secret("secret".as_ref()),
+
+    let key_store = KeyStore {
+        signing_kid: config.signing_kid.clone(),
+        signing_algorithm,
+        signing_key,
+        verifying_keys,
+    };
+    match KEYS.set(key_store) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(TokenError::InvalidKeyConfig(
+            "keys already initialized".to_string(),
+        )),
+    }
+}
+
+fn load_key(config: &JwtKeyConfig) -> Result<(Option<EncodingKey>, DecodingKey), TokenError> {
+    match config.algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            let secret = load_secret(config)?;
+            Ok((
+                Some(EncodingKey::from_secret(&secret)),
+                DecodingKey::from_secret(&secret),
+            ))
+        }
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::EdDSA => {
+            let is_rsa = config.algorithm != Algorithm::EdDSA;
+
+            let public_key = match &config.public_key_path {
+                Some(path) => read_key_file(path)?,
+                None => {
+                    return Err(TokenError::InvalidKeyConfig(format!(
+                        "key {} has no public_key_path",
+                        config.kid
+                    )))
+                }
+            };
+            let decoding_key = if is_rsa {
+                DecodingKey::from_rsa_pem(&public_key)?
+            } else {
+                DecodingKey::from_ed_pem(&public_key)?
+            };
+
+            // Keys kept only to verify tokens from before a rotation need no private part
+            let encoding_key = match &config.private_key_path {
+                Some(path) => {
+                    let private_key = read_key_file(path)?;
+                    if is_rsa {
+                        Some(EncodingKey::from_rsa_pem(&private_key)?)
+                    } else {
+                        Some(EncodingKey::from_ed_pem(&private_key)?)
+                    }
+                }
+                None => None,
+            };
+
+            Ok((encoding_key, decoding_key))
+        }
+        other => Err(TokenError::InvalidKeyConfig(format!(
+            "algorithm {:?} is not supported",
+            other
+        ))),
+    }
+}
+
+// Secret from the config, an env var or a file, in that order
+fn load_secret(config: &JwtKeyConfig) -> Result<Vec<u8>, TokenError> {
+    if let Some(secret) = &config.secret {
+        return Ok(secret.as_bytes().to_vec());
+    }
+    if let Some(secret) = config
+        .secret_env
+        .as_ref()
+        .and_then(|name| env::var(name).ok())
+    {
+        return Ok(secret.into_bytes());
+    }
+    if let Some(path) = &config.secret_file {
+        let secret = read_key_file(path)?;
+        return Ok(String::from_utf8_lossy(&secret).trim().as_bytes().to_vec());
+    }
+
+    // Nothing configured: tokens only stay valid until the server restarts
+    warn!(
+        "No secret configured for key {}, using a random one",
+        config.kid
     );
-    return token;
+    Ok(rand::thread_rng().gen::<[u8; 32]>().to_vec())
 }
 
-pub fn decode_token(token: &String) -> Result<TokenData<Claims>, String> {
-    match decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret("secret".as_ref()),
-        &Validation::new(Algorithm::HS256),
-    ) {
-        Ok(token) => Ok(token),
-        Err(error) => Err(error.to_string()),
+fn read_key_file(path: &String) -> Result<Vec<u8>, TokenError> {
+    fs::read(path).map_err(|error| TokenError::KeyFile(path.clone(), error))
+}
+
+fn get_keys() -> Result<&'static KeyStore, TokenError> {
+    match KEYS.get() {
+        Some(keys) => Ok(keys),
+        None => Err(TokenError::InvalidKeyConfig(
+            "keys are not initialized".to_string(),
+        )),
     }
 }
 
-#[allow(non_snake_case)]
-#[allow(non_camel_case_types)]
+// Unit tests sign with a fixed secret, whichever test gets here first sets it up
+#[cfg(test)]
+pub(crate) fn init_test_keys() {
+    let _ = init_keys(&JwtConfig {
+        signing_kid: "test".to_string(),
+        keys: vec![JwtKeyConfig {
+            kid: "test".to_string(),
+            algorithm: Algorithm::HS256,
+            secret: Some("test secret".to_string()),
+            secret_env: None,
+            secret_file: None,
+            private_key_path: None,
+            public_key_path: None,
+        }],
+    });
+}
+
+pub fn generate_token(session: &Session) -> Result<String, TokenError> {
+    let keys = get_keys()?;
+
+    let new_claims = Claims {
+        typ: SESSION_TOKEN_TYPE.to_string(),
+        sid: session.id.clone(),
+        id: session.user_id.clone(),
+        exp: session.expires_at as usize,
+    };
+
+    let mut header = Header::new(keys.signing_algorithm);
+    header.kid = Some(keys.signing_kid.clone());
+    let token = encode(&header, &new_claims, &keys.signing_key)?;
+    Ok(token)
+}
+
+pub fn generate_invite_token(invite: &Invite) -> Result<String, TokenError> {
+    let keys = get_keys()?;
+
+    let new_claims = InviteClaims {
+        typ: INVITE_TOKEN_TYPE.to_string(),
+        iid: invite.id.clone(),
+        room: invite.room_id.clone(),
+        exp: invite.expires_at as usize,
+    };
+
+    let mut header = Header::new(keys.signing_algorithm);
+    header.kid = Some(keys.signing_kid.clone());
+    let token = encode(&header, &new_claims, &keys.signing_key)?;
+    Ok(token)
+}
+
+pub fn decode_token(token: &str) -> Result<TokenData<Claims>, TokenError> {
+    let token_data = decode_signed::<Claims>(token)?;
+    check_type(&token_data.claims.typ, SESSION_TOKEN_TYPE)?;
+    Ok(token_data)
+}
+
+pub fn decode_invite_token(token: &str) -> Result<TokenData<InviteClaims>, TokenError> {
+    let token_data = decode_signed::<InviteClaims>(token)?;
+    check_type(&token_data.claims.typ, INVITE_TOKEN_TYPE)?;
+    Ok(token_data)
+}
+
+fn check_type(typ: &str, expected: &str) -> Result<(), TokenError> {
+    match typ == expected {
+        true => Ok(()),
+        false => Err(TokenError::WrongType(typ.to_string())),
+    }
+}
+
+fn decode_signed<T: DeserializeOwned>(token: &str) -> Result<TokenData<T>, TokenError> {
+    let keys = get_keys()?;
+
+    // Tokens without a kid were signed before key ids existed, try the current key
+    let kid = decode_header(token)?
+        .kid
+        .unwrap_or_else(|| keys.signing_kid.clone());
+    let (algorithm, decoding_key) = match keys.verifying_keys.get(&kid) {
+        Some(key) => key,
+        None => return Err(TokenError::UnknownKeyId(kid)),
+    };
+
+    let token_data = decode::<T>(token, decoding_key, &Validation::new(*algorithm))?;
+    Ok(token_data)
+}
+
+// User state lives server-side and is looked up through the session on every command
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
+    pub typ: String,
+    pub sid: String,
     pub id: String,
-    pub name: String,
-    pub avatarPath: String,
-    pub roomId: String,
-    pub isHost: bool,
-    pub userColor: String,
     pub exp: usize,
 }
+
+// Invites carry no user, the invite id is looked up to make them single use
+#[derive(Serialize, Deserialize)]
+pub struct InviteClaims {
+    pub typ: String,
+    pub iid: String,
+    pub room: String,
+    pub exp: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign<T: Serialize>(claims: &T) -> String {
+        let keys = get_keys().unwrap();
+        let mut header = Header::new(keys.signing_algorithm);
+        header.kid = Some(keys.signing_kid.clone());
+        encode(&header, claims, &keys.signing_key).unwrap()
+    }
+
+    fn claims(typ: &str) -> Claims {
+        Claims {
+            typ: typ.to_string(),
+            sid: "session".to_string(),
+            id: "user".to_string(),
+            exp: usize::MAX / 2,
+        }
+    }
+
+    fn invite_claims(typ: &str) -> InviteClaims {
+        InviteClaims {
+            typ: typ.to_string(),
+            iid: "invite".to_string(),
+            room: "room".to_string(),
+            exp: usize::MAX / 2,
+        }
+    }
+
+    #[test]
+    fn decodes_tokens_of_the_expected_type() {
+        init_test_keys();
+        let token_data = decode_token(&sign(&claims(SESSION_TOKEN_TYPE))).unwrap();
+        assert_eq!(token_data.claims.sid, "session");
+        let token_data = decode_invite_token(&sign(&invite_claims(INVITE_TOKEN_TYPE))).unwrap();
+        assert_eq!(token_data.claims.iid, "invite");
+    }
+
+    #[test]
+    fn rejects_tokens_of_another_type() {
+        init_test_keys();
+        assert!(matches!(
+            decode_token(&sign(&claims(INVITE_TOKEN_TYPE))),
+            Err(TokenError::WrongType(_))
+        ));
+        assert!(matches!(
+            decode_invite_token(&sign(&invite_claims(SESSION_TOKEN_TYPE))),
+            Err(TokenError::WrongType(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_tokens_without_a_type() {
+        init_test_keys();
+        let mut claims = serde_json::to_value(claims(SESSION_TOKEN_TYPE)).unwrap();
+        claims.as_object_mut().unwrap().remove("typ");
+        assert!(decode_token(&sign(&claims)).is_err());
+    }
+}
@@ -1,6 +1,24 @@
+pub mod config;
+pub mod errors;
 pub mod handlers;
 pub mod helpers;
+pub mod http;
+pub mod import;
+pub mod invites;
 pub mod jwtoken;
 pub mod loggers;
+pub mod metrics;
 pub mod models;
+pub mod packs;
+pub mod passwords;
+pub mod peer_queue;
+pub mod rate_limit;
+pub mod room_codes;
+pub mod room_list;
 pub mod server_messages;
+pub mod sessions;
+pub mod shutdown;
+pub mod state;
+pub mod storage;
+pub mod tls;
+pub mod validation;
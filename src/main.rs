@@ -1,70 +1,101 @@
-use futures_channel::mpsc::UnboundedSender;
 use log::info;
-use native_tls::Identity;
 use quiz_game_rust::{
+    config::{get_config, init_config},
     handlers::connection_handler::handle_connection,
+    jwtoken::init_keys,
     loggers::file_logger::init_file_logger,
-    models::lobby::{Room, User},
+    packs::scan_packs,
+    room_list::watch_room_list,
+    shutdown::{drain, wait_for_signal},
+    state::AppState,
+    storage::open_database,
+    tls::{load_tls_acceptor, watch_tls_certificates},
 };
 use std::{
-    collections::HashMap,
     env,
     io::Error as IoError,
+    path::Path,
     sync::{Arc, Mutex},
 };
 use tokio::net::TcpListener;
-use tungstenite::protocol::Message;
+use tokio_native_tls::TlsAcceptor;
 
-type Tx = UnboundedSender<Message>;
-type TxTimeout = UnboundedSender<bool>;
-type PeerMap = Arc<Mutex<HashMap<String, Tx>>>;
-type UserList = Arc<Mutex<Vec<User>>>;
-type RoomList = Arc<Mutex<Vec<Room>>>;
-type GameList = Arc<Mutex<HashMap<String, Tx>>>;
-type UserTimeoutList = Arc<Mutex<HashMap<String, TxTimeout>>>;
+type TlsAcceptorHandle = Arc<Mutex<TlsAcceptor>>;
 
 #[tokio::main]
 async fn main() -> Result<(), IoError> {
     init_file_logger().unwrap();
     info!("App started!");
+    init_config().unwrap();
+    init_keys(&get_config().jwt).unwrap();
 
     let addr = env::args()
         .nth(1)
-        .unwrap_or_else(|| "127.0.0.1:9001".to_string());
+        .unwrap_or_else(|| get_config().address.clone());
 
-    let state = PeerMap::new(Mutex::new(HashMap::new()));
+    let state = AppState::new(
+        scan_packs(Path::new(&get_config().packs_dir)),
+        open_database(&get_config().database_path).expect("Failed to open database"),
+    );
 
-    let try_socket = TcpListener::bind(&addr).await;
-    let listener = try_socket.expect("Failed to bind");
-    info!("Listening on: {}", addr);
+    tokio::spawn(watch_room_list(state.clone()));
 
-    let users = UserList::new(Mutex::new(Vec::new()));
-    let rooms = RoomList::new(Mutex::new(Vec::new()));
-    let games = GameList::new(Mutex::new(HashMap::new()));
-    let user_timeouts = UserTimeoutList::new(Mutex::new(HashMap::new()));
+    let mut listeners = Vec::new();
+    let serve_plain = match &get_config().tls {
+        Some(tls_config) => {
+            let acceptor = TlsAcceptorHandle::new(Mutex::new(
+                load_tls_acceptor(tls_config).expect("Failed to load TLS certificate"),
+            ));
+            tokio::spawn(watch_tls_certificates(tls_config.clone(), acceptor.clone()));
 
-    // let der = include_bytes!("identity.p12");
-    // let cert = Identity::from_pkcs12(der, "mypass").expect("can't certify");
-    // let tls_acceptor = tokio_native_tls::TlsAcceptor::from(
-    //     native_tls::TlsAcceptor::builder(cert)
-    //         .build()
-    //         .expect("can't make tls acceptor"),
-    // );
+            let listener = TcpListener::bind(&tls_config.address)
+                .await
+                .expect("Failed to bind");
+            info!("Listening for WSS on: {}", &tls_config.address);
+            listeners.push(tokio::spawn(accept_connections(
+                listener,
+                state.clone(),
+                Some(acceptor),
+            )));
+
+            tls_config.serve_plain
+        }
+        None => true,
+    };
+
+    if serve_plain {
+        let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
+        info!("Listening on: {}", addr);
+        listeners.push(tokio::spawn(accept_connections(
+            listener,
+            state.clone(),
+            None,
+        )));
+    }
+
+    wait_for_signal().await;
+    info!("Shutting down, no longer accepting connections");
+    for listener in &listeners {
+        listener.abort();
+    }
+
+    drain(&state, &get_config().shutdown).await;
+    info!("Shutdown complete");
 
+    Ok(())
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    state: AppState,
+    acceptor: Option<TlsAcceptorHandle>,
+) {
     while let Ok((stream, addr)) = listener.accept().await {
         tokio::spawn(handle_connection(
-            (
-                state.clone(),
-                users.clone(),
-                rooms.clone(),
-                games.clone(),
-                user_timeouts.clone(),
-            ),
+            state.clone(),
             stream,
             addr,
-            // tls_acceptor.clone(),
+            acceptor.clone(),
         ));
     }
-
-    Ok(())
 }
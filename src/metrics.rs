@@ -0,0 +1,243 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use crate::state::AppState;
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+// Upper bounds in seconds, answers can't take longer than the question timer
+const ANSWER_LATENCY_BUCKETS: [f64; 10] = [0.5, 1.0, 2.0, 3.0, 5.0, 7.5, 10.0, 15.0, 20.0, 30.0];
+
+// Counters collected while the server runs. Gauges (connections, rooms, ...) are
+// read from the lists when /metrics is scraped
+#[derive(Default)]
+pub struct Metrics {
+    commands: Mutex<BTreeMap<String, u64>>,
+    errors: Mutex<BTreeMap<i32, u64>>,
+    broadcasts: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_dropped: AtomicU64,
+    timeout_removals: AtomicU64,
+    answer_latency: Mutex<Histogram>,
+}
+
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; ANSWER_LATENCY_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+impl Metrics {
+    pub fn record_command(&self, command_name: &str) {
+        *self
+            .commands
+            .lock()
+            .unwrap()
+            .entry(command_name.to_string())
+            .or_insert(0) += 1;
+    }
+
+    pub fn record_error(&self, error_code: i32) {
+        *self.errors.lock().unwrap().entry(error_code).or_insert(0) += 1;
+    }
+
+    pub fn record_broadcast(&self) {
+        self.broadcasts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_messages_sent(&self, count: usize) {
+        self.messages_sent
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_message_dropped(&self) {
+        self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_timeout_removal(&self) {
+        self.timeout_removals.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_answer_latency(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let mut histogram = self.answer_latency.lock().unwrap();
+        for (index, bound) in ANSWER_LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= *bound {
+                histogram.counts[index] += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+}
+
+// Prometheus text exposition format
+pub fn render_metrics(state: AppState) -> String {
+    let metrics = metrics();
+    let mut output = String::new();
+
+    let (connections, queued_messages, max_queue_depth) = {
+        let peers = state.peers.lock().unwrap();
+        let depths: Vec<usize> = peers.values().map(|peer| peer.depth()).collect();
+        (
+            peers.len(),
+            depths.iter().sum::<usize>(),
+            depths.iter().max().cloned().unwrap_or(0),
+        )
+    };
+    write_gauge(
+        &mut output,
+        "quiz_connections",
+        "Open WebSocket connections",
+        connections,
+    );
+    write_gauge(
+        &mut output,
+        "quiz_users",
+        "Users in rooms, including disconnected ones in their grace period",
+        state.users.lock().unwrap().len(),
+    );
+    write_gauge(
+        &mut output,
+        "quiz_rooms",
+        "Open rooms",
+        state.rooms.lock().unwrap().len(),
+    );
+    write_gauge(
+        &mut output,
+        "quiz_games_running",
+        "Games in progress",
+        state.games.lock().unwrap().len(),
+    );
+    write_gauge(
+        &mut output,
+        "quiz_peer_queue_messages",
+        "Messages waiting in outgoing peer queues",
+        queued_messages,
+    );
+    write_gauge(
+        &mut output,
+        "quiz_peer_queue_max_depth",
+        "Deepest outgoing peer queue",
+        max_queue_depth,
+    );
+
+    writeln!(
+        output,
+        "# HELP quiz_commands_total Commands processed by type"
+    )
+    .unwrap();
+    writeln!(output, "# TYPE quiz_commands_total counter").unwrap();
+    for (command, count) in metrics.commands.lock().unwrap().iter() {
+        writeln!(
+            output,
+            "quiz_commands_total{{command=\"{}\"}} {}",
+            command, count
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        output,
+        "# HELP quiz_error_responses_total Error responses sent by error code"
+    )
+    .unwrap();
+    writeln!(output, "# TYPE quiz_error_responses_total counter").unwrap();
+    for (code, count) in metrics.errors.lock().unwrap().iter() {
+        writeln!(
+            output,
+            "quiz_error_responses_total{{code=\"{}\"}} {}",
+            code, count
+        )
+        .unwrap();
+    }
+
+    write_counter(
+        &mut output,
+        "quiz_broadcasts_total",
+        "Broadcasts to rooms or all connections",
+        metrics.broadcasts.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut output,
+        "quiz_messages_sent_total",
+        "Messages queued to peers",
+        metrics.messages_sent.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut output,
+        "quiz_messages_dropped_total",
+        "Timer ticks dropped from full peer queues",
+        metrics.messages_dropped.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut output,
+        "quiz_timeout_removals_total",
+        "Users removed after their reconnect grace period",
+        metrics.timeout_removals.load(Ordering::Relaxed),
+    );
+
+    let histogram = metrics.answer_latency.lock().unwrap();
+    writeln!(
+        output,
+        "# HELP quiz_answer_latency_seconds Time from answers being shown to a player answering"
+    )
+    .unwrap();
+    writeln!(output, "# TYPE quiz_answer_latency_seconds histogram").unwrap();
+    for (bound, count) in ANSWER_LATENCY_BUCKETS.iter().zip(histogram.counts.iter()) {
+        writeln!(
+            output,
+            "quiz_answer_latency_seconds_bucket{{le=\"{}\"}} {}",
+            bound, count
+        )
+        .unwrap();
+    }
+    writeln!(
+        output,
+        "quiz_answer_latency_seconds_bucket{{le=\"+Inf\"}} {}",
+        histogram.count
+    )
+    .unwrap();
+    writeln!(output, "quiz_answer_latency_seconds_sum {}", histogram.sum).unwrap();
+    writeln!(
+        output,
+        "quiz_answer_latency_seconds_count {}",
+        histogram.count
+    )
+    .unwrap();
+
+    output
+}
+
+fn write_gauge(output: &mut String, name: &str, help: &str, value: usize) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} gauge", name).unwrap();
+    writeln!(output, "{} {}", name, value).unwrap();
+}
+
+fn write_counter(output: &mut String, name: &str, help: &str, value: u64) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} counter", name).unwrap();
+    writeln!(output, "{} {}", name, value).unwrap();
+}
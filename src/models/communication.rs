@@ -3,19 +3,47 @@
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use super::{game::Answer, lobby::User};
+use super::{
+    game::{Answer, AnswerPayload, GamePhase, PackInfo, Standing},
+    lobby::{PublicRoomInfo, RoomListFilter, RoomSettings, RoomSettingsUpdate, User},
+};
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "response", content = "data")]
 pub enum Response {
+    connectionInfoResponse {
+        heartbeatIntervalSec: u64,
+        maxMissedPongs: u32,
+    },
     createRoomResponse {
         token: String,
+        roomCode: String,
         userList: Vec<User>,
+        settings: RoomSettings,
     },
     joinRoomResponse {
         token: String,
+        roomCode: String,
         userList: Vec<User>,
+        settings: RoomSettings,
+    },
+    roomSettingsChanged {
+        settings: RoomSettings,
+    },
+    inviteCreated {
+        inviteId: String,
+        inviteToken: String,
+        roomCode: String,
+        // Unix timestamp (seconds)
+        expiresAt: i64,
+    },
+    invitesRevoked {
+        inviteIds: Vec<String>,
+    },
+    tokenResponse {
+        token: String,
     },
     updateUserList {
         userList: Vec<User>,
@@ -25,57 +53,220 @@ pub enum Response {
         text: String,
     },
     startGame {},
+    packListResponse {
+        packs: Vec<PackInfo>,
+    },
+    roomListResponse {
+        rooms: Vec<PublicRoomInfo>,
+        page: usize,
+        pageSize: usize,
+        totalRooms: usize,
+    },
+    // Reply to commands that have no other direct response
+    ack {
+        command: String,
+    },
+    serverShutdown {
+        reason: Option<String>,
+        // Unix timestamp (seconds) after which running games are ended
+        deadline: i64,
+    },
+    // Built from ServerError, errorCode and reason are stable, errorText is for humans
     errorResponse {
         errorText: String,
         errorCode: i32,
+        reason: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        details: Option<Value>,
     },
     questionResponse {
         question: String,
     },
     answersResponse {
+        questionType: String,
         answers: Vec<Answer>,
         timer: i32,
     },
     timerResponse {
         timer: i32,
     },
+    closedEarlyResponse {
+        timer: i32,
+    },
+    gamePausedResponse {},
+    gameResumedResponse {},
+    questionSkippedResponse {},
+    gameStateResponse {
+        phase: GamePhase,
+        questionIndex: i32,
+        questionCount: i32,
+        question: String,
+        questionType: String,
+        answers: Vec<Answer>,
+        timer: i32,
+        paused: bool,
+        ownAnswer: Option<AnswerPayload>,
+        correctAnswer: Option<AnswerPayload>,
+        scores: HashMap<String, i32>,
+    },
+    gameOverResponse {
+        standings: Vec<Standing>,
+        winners: Vec<String>,
+        endedEarly: bool,
+    },
     correctAnswerResponse {
-        answers: HashMap<String, i32>,
-        correctAnswer: i32,
+        answers: HashMap<String, Option<AnswerPayload>>,
+        correctAnswer: AnswerPayload,
     },
     scoresResponse {
         scores: HashMap<String, i32>,
+        pointsEarned: HashMap<String, i32>,
     },
 }
 
 pub enum Command {
-    UnauthorizedCommand(UnauthorizedCommand),
+    UnauthorizedCommand(UnauthorizedCommandRequest),
     CommandTokenPair(CommandTokenPair),
 }
 
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::UnauthorizedCommand(command) => command.command.name(),
+            Command::CommandTokenPair(command) => command.command.name(),
+        }
+    }
+
+    pub fn request_id(&self) -> Option<String> {
+        match self {
+            Command::UnauthorizedCommand(command) => command.requestId.clone(),
+            Command::CommandTokenPair(command) => command.requestId.clone(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum UnauthorizedCommand {
     createRoom {
         name: String,
         avatarPath: String,
+        #[serde(default)]
+        settings: RoomSettings,
     },
+    // roomId is the room's id or its join code. Private rooms also need the
+    // password or an invite token from the host
     joinRoom {
         name: String,
         avatarPath: String,
         roomId: String,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        inviteToken: Option<String>,
     },
     heartbeat {},
+    // With subscribe the same page is pushed again whenever it changes
+    listRooms {
+        #[serde(default)]
+        filter: RoomListFilter,
+        #[serde(default)]
+        page: usize,
+        #[serde(default = "default_room_page_size")]
+        pageSize: usize,
+        #[serde(default)]
+        subscribe: bool,
+    },
+    unsubscribeRooms {},
+}
+
+fn default_room_page_size() -> usize {
+    20
+}
+
+impl UnauthorizedCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnauthorizedCommand::createRoom { .. } => "createRoom",
+            UnauthorizedCommand::joinRoom { .. } => "joinRoom",
+            UnauthorizedCommand::heartbeat {} => "heartbeat",
+            UnauthorizedCommand::listRooms { .. } => "listRooms",
+            UnauthorizedCommand::unsubscribeRooms {} => "unsubscribeRooms",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum AuthorizedCommand {
     reconnectRoom {},
-    startGame { packPath: String },
+    // Without packId the room's default pack is started
+    startGame {
+        #[serde(default)]
+        packId: Option<String>,
+    },
+    listPacks {},
     getUserList {},
-    broadcastMessage { text: String },
-    writeAnswer { answer: i32 },
-    changeUsername { newName: String },
-    changeAvatar { newAvatarPath: String },
+    broadcastMessage {
+        text: String,
+    },
+    writeAnswer {
+        answer: AnswerPayload,
+    },
+    changeUsername {
+        newName: String,
+    },
+    changeAvatar {
+        newAvatarPath: String,
+    },
+    pauseGame {},
+    resumeGame {},
+    skipQuestion {},
+    endGame {},
+    // Partial update: omitted fields keep their current value, an explicit null clears
+    // optional fields such as the password
+    updateRoomSettings {
+        settings: RoomSettingsUpdate,
+    },
+    // Single use, valid for validForSec (a day by default, a week at most)
+    createInvite {
+        #[serde(default)]
+        validForSec: Option<i64>,
+    },
+    // Without inviteId every outstanding invite of the room is revoked
+    revokeInvites {
+        #[serde(default)]
+        inviteId: Option<String>,
+    },
+}
+
+impl AuthorizedCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuthorizedCommand::reconnectRoom {} => "reconnectRoom",
+            AuthorizedCommand::startGame { .. } => "startGame",
+            AuthorizedCommand::listPacks {} => "listPacks",
+            AuthorizedCommand::getUserList {} => "getUserList",
+            AuthorizedCommand::broadcastMessage { .. } => "broadcastMessage",
+            AuthorizedCommand::writeAnswer { .. } => "writeAnswer",
+            AuthorizedCommand::changeUsername { .. } => "changeUsername",
+            AuthorizedCommand::changeAvatar { .. } => "changeAvatar",
+            AuthorizedCommand::pauseGame {} => "pauseGame",
+            AuthorizedCommand::resumeGame {} => "resumeGame",
+            AuthorizedCommand::skipQuestion {} => "skipQuestion",
+            AuthorizedCommand::endGame {} => "endGame",
+            AuthorizedCommand::updateRoomSettings { .. } => "updateRoomSettings",
+            AuthorizedCommand::createInvite { .. } => "createInvite",
+            AuthorizedCommand::revokeInvites { .. } => "revokeInvites",
+        }
+    }
+}
+
+// requestId is chosen by the client and echoed on the direct reply to the command
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UnauthorizedCommandRequest {
+    #[serde(flatten)]
+    pub command: UnauthorizedCommand,
+    #[serde(default)]
+    pub requestId: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -83,4 +274,6 @@ pub struct CommandTokenPair {
     #[serde(flatten)]
     pub command: AuthorizedCommand,
     pub token: String,
+    #[serde(default)]
+    pub requestId: Option<String>,
 }
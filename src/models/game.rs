@@ -1,4 +1,7 @@
-use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Answer {
@@ -6,24 +9,371 @@ pub struct Answer {
     pub text: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Question {
     pub text: String,
-    pub answers: Vec<Answer>,
-    pub correct_answer: i32,
     pub duration_sec: i32,
+    #[serde(flatten, deserialize_with = "deserialize_question_kind")]
+    pub kind: QuestionKind,
 }
 
-#[derive(Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum QuestionKind {
+    singleChoice {
+        answers: Vec<Answer>,
+        correct_answer: i32,
+    },
+    trueFalse {
+        correct_answer: bool,
+    },
+    multiSelect {
+        answers: Vec<Answer>,
+        correct_answers: Vec<i32>,
+    },
+    numeric {
+        correct_value: f64,
+    },
+    freeText {
+        accepted_answers: Vec<String>,
+    },
+}
+
+impl QuestionKind {
+    pub fn name(&self) -> String {
+        match self {
+            QuestionKind::singleChoice { .. } => "singleChoice".to_string(),
+            QuestionKind::trueFalse { .. } => "trueFalse".to_string(),
+            QuestionKind::multiSelect { .. } => "multiSelect".to_string(),
+            QuestionKind::numeric { .. } => "numeric".to_string(),
+            QuestionKind::freeText { .. } => "freeText".to_string(),
+        }
+    }
+
+    // Options shown to players, empty for types answered without a list
+    pub fn answers(&self) -> Vec<Answer> {
+        match self {
+            QuestionKind::singleChoice { answers, .. } => answers.clone(),
+            QuestionKind::multiSelect { answers, .. } => answers.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn correct_answer(&self) -> AnswerPayload {
+        match self {
+            QuestionKind::singleChoice { correct_answer, .. } => {
+                AnswerPayload::choice(*correct_answer)
+            }
+            QuestionKind::trueFalse { correct_answer } => AnswerPayload::boolean(*correct_answer),
+            QuestionKind::multiSelect {
+                correct_answers, ..
+            } => AnswerPayload::choices(correct_answers.clone()),
+            QuestionKind::numeric { correct_value } => AnswerPayload::number(*correct_value),
+            QuestionKind::freeText { accepted_answers } => {
+                AnswerPayload::text(accepted_answers.first().cloned().unwrap_or_default())
+            }
+        }
+    }
+}
+
+// Questions without a "type" field are single choice, as in packs made before question types
+fn deserialize_question_kind<'de, D>(deserializer: D) -> Result<QuestionKind, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut fields = Map::deserialize(deserializer)?;
+    if !fields.contains_key("type") {
+        fields.insert("type".to_string(), Value::from("singleChoice"));
+    }
+    QuestionKind::deserialize(Value::Object(fields)).map_err(D::Error::custom)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Pack {
     pub name: String,
+    #[serde(default)]
+    pub metadata: PackMetadata,
     pub questions: Vec<Question>,
+    #[serde(default)]
+    pub scoring: Scoring,
+    // Cut the countdown short once every connected player has answered
+    #[serde(default)]
+    pub end_when_all_answered: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PackMetadata {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackInfo {
+    pub id: String,
+    pub name: String,
+    pub questionCount: i32,
+    pub metadata: PackMetadata,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum ScoringCurve {
+    flat,
+    linear,
+    quadratic,
+}
+
+// Every correct answer is worth a fixed 100 points unless the pack picks a
+// time-weighted curve, then points fall from max_points towards min_points
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Scoring {
+    pub max_points: i32,
+    pub min_points: i32,
+    pub curve: ScoringCurve,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Scoring {
+            max_points: 100,
+            min_points: 50,
+            curve: ScoringCurve::flat,
+        }
+    }
+}
+
+impl Scoring {
+    // Points for a correct answer given with `remaining` time left out of `total`
+    pub fn points(&self, remaining: Duration, total: Duration) -> i32 {
+        let fraction = if total.is_zero() {
+            0.0
+        } else {
+            (remaining.as_secs_f64() / total.as_secs_f64()).clamp(0.0, 1.0)
+        };
+        let weight = match self.curve {
+            ScoringCurve::flat => 1.0,
+            ScoringCurve::linear => fraction,
+            ScoringCurve::quadratic => fraction * fraction,
+        };
+
+        let bonus = (self.max_points - self.min_points) as f64 * weight;
+        self.min_points + bonus.round() as i32
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum AnswerPayload {
+    choice(i32),
+    choices(Vec<i32>),
+    boolean(bool),
+    number(f64),
+    text(String),
+}
+
+impl AnswerPayload {
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            AnswerPayload::choice(value) => Some(*value as f64),
+            AnswerPayload::number(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Standing {
+    pub rank: i32,
+    pub userId: String,
+    pub score: i32,
+    pub correctAnswers: i32,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum GamePhase {
+    question,
+    answering,
+    reveal,
+}
+
+// Snapshot of a running game, kept up to date so reconnecting players can catch up
+#[derive(Clone)]
+pub struct GameState {
+    pub phase: GamePhase,
+    pub question_index: i32,
+    pub question_count: i32,
+    pub question: String,
+    pub question_type: String,
+    pub answers: Vec<Answer>,
+    pub timer: i32,
+    pub paused: bool,
+    pub player_answers: HashMap<String, Option<AnswerPayload>>,
+    pub correct_answer: Option<AnswerPayload>,
+    pub scores: HashMap<String, i32>,
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Serialize, Deserialize)]
-pub struct GameCommand {
-    pub user_id: String,
-    pub answer: i32,
+#[serde(tag = "command")]
+pub enum GameCommand {
+    writeAnswer {
+        user_id: String,
+        answer: AnswerPayload,
+    },
+    pauseGame {},
+    resumeGame {},
+    skipQuestion {},
+    endGame {},
+}
+
+impl GameCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameCommand::writeAnswer { .. } => "writeAnswer",
+            GameCommand::pauseGame {} => "pauseGame",
+            GameCommand::resumeGame {} => "resumeGame",
+            GameCommand::skipQuestion {} => "skipQuestion",
+            GameCommand::endGame {} => "endGame",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scoring(curve: ScoringCurve) -> Scoring {
+        Scoring {
+            max_points: 1000,
+            min_points: 500,
+            curve,
+        }
+    }
+
+    #[test]
+    fn default_scoring_is_fixed() {
+        let scoring = Scoring::default();
+        let total = Duration::from_secs(10);
+        assert_eq!(scoring.points(total, total), 100);
+        assert_eq!(scoring.points(Duration::from_secs(3), total), 100);
+        assert_eq!(scoring.points(Duration::ZERO, total), 100);
+    }
+
+    #[test]
+    fn packs_without_scoring_keep_fixed_points() {
+        let pack: Pack = serde_json::from_str(r#"{"name": "pack", "questions": []}"#).unwrap();
+        assert!(matches!(pack.scoring.curve, ScoringCurve::flat));
+
+        let pack: Pack = serde_json::from_str(
+            r#"{"name": "pack", "questions": [], "scoring": {"curve": "linear"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(pack.scoring.curve, ScoringCurve::linear));
+        assert_eq!(pack.scoring.max_points, 100);
+    }
+
+    #[test]
+    fn linear_scoring_follows_time_left() {
+        let scoring = scoring(ScoringCurve::linear);
+        let total = Duration::from_secs(10);
+        assert_eq!(scoring.points(total, total), 1000);
+        assert_eq!(scoring.points(Duration::from_secs(5), total), 750);
+        assert_eq!(scoring.points(Duration::ZERO, total), 500);
+    }
+
+    #[test]
+    fn quadratic_scoring_drops_faster() {
+        let scoring = scoring(ScoringCurve::quadratic);
+        let total = Duration::from_secs(10);
+        assert_eq!(scoring.points(total, total), 1000);
+        assert_eq!(scoring.points(Duration::from_secs(5), total), 625);
+        assert_eq!(scoring.points(Duration::ZERO, total), 500);
+    }
+
+    #[test]
+    fn points_stay_in_range() {
+        let scoring = scoring(ScoringCurve::linear);
+        assert_eq!(
+            scoring.points(Duration::from_secs(20), Duration::from_secs(10)),
+            1000
+        );
+        assert_eq!(scoring.points(Duration::from_secs(5), Duration::ZERO), 500);
+    }
+
+    fn question(json: &str) -> Question {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn questions_without_type_are_single_choice() {
+        let question = question(
+            r#"{"text": "q", "duration_sec": 10,
+                "answers": [{"number": 0, "text": "a"}, {"number": 1, "text": "b"}],
+                "correct_answer": 1}"#,
+        );
+        match question.kind {
+            QuestionKind::singleChoice {
+                answers,
+                correct_answer,
+            } => {
+                assert_eq!(answers.len(), 2);
+                assert_eq!(correct_answer, 1);
+            }
+            _ => panic!("expected a single choice question"),
+        }
+    }
+
+    #[test]
+    fn questions_are_read_by_type() {
+        let kind = question(
+            r#"{"text": "q", "duration_sec": 10, "type": "trueFalse", "correct_answer": false}"#,
+        )
+        .kind;
+        assert!(matches!(
+            kind,
+            QuestionKind::trueFalse {
+                correct_answer: false
+            }
+        ));
+
+        let kind = question(
+            r#"{"text": "q", "duration_sec": 10, "type": "numeric", "correct_value": 1.5}"#,
+        )
+        .kind;
+        assert!(matches!(kind, QuestionKind::numeric { correct_value } if correct_value == 1.5));
+
+        let kind = question(
+            r#"{"text": "q", "duration_sec": 10, "type": "freeText", "accepted_answers": ["Paris"]}"#,
+        )
+        .kind;
+        assert_eq!(kind.name(), "freeText");
+        assert_eq!(
+            kind.correct_answer(),
+            AnswerPayload::text("Paris".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_types_and_missing_fields() {
+        assert!(serde_json::from_str::<Question>(
+            r#"{"text": "q", "duration_sec": 10, "type": "essay"}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<Question>(
+            r#"{"text": "q", "duration_sec": 10, "type": "multiSelect", "answers": []}"#
+        )
+        .is_err());
+    }
 }
@@ -2,7 +2,10 @@ use rand::{
     distributions::{Distribution, Standard},
     Rng,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::game::ScoringCurve;
+use crate::passwords::PasswordHash;
 
 pub trait HasId {
     fn get_id(&self) -> String;
@@ -21,23 +24,169 @@ pub struct User {
 }
 impl HasId for User {
     fn get_id(&self) -> String {
-        return self.id.clone();
+        self.id.clone()
     }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Room {
     pub id: String,
-    pub max_players: i32,
+    // Short code players type in to join, unique among live rooms
+    pub code: String,
     pub host_id: String,
     pub current_players: i32,
+    pub settings: RoomSettings,
+    // Pack of the running or last started game
+    pub pack_id: Option<String>,
+}
+
+// Chosen with createRoom, the host can change them with updateRoomSettings in the lobby
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RoomSettings {
+    pub maxPlayers: i32,
+    // Started when startGame names no pack
+    pub defaultPackId: Option<String>,
+    // Replaces the duration of every question
+    pub questionDurationSec: Option<i32>,
+    // Replaces the scoring curve of the pack
+    pub scoringMode: Option<ScoringCurve>,
+    // Public rooms are shown by listRooms, private ones need the id or code to join
+    pub public: bool,
+    // Only read from clients, the room keeps passwordHash and clients see hasPassword
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    #[serde(skip)]
+    pub passwordHash: Option<PasswordHash>,
+    #[serde(skip_deserializing)]
+    pub hasPassword: bool,
+    // Only players with an invite from the host (or the password, if set) can join
+    pub inviteOnly: bool,
+    pub lateJoin: LateJoinPolicy,
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        RoomSettings {
+            maxPlayers: 6,
+            defaultPackId: None,
+            questionDurationSec: None,
+            scoringMode: None,
+            public: false,
+            password: None,
+            passwordHash: None,
+            hasPassword: false,
+            inviteOnly: false,
+            lateJoin: LateJoinPolicy::never,
+        }
+    }
+}
+
+// Sent with updateRoomSettings. Left out fields keep their current value, null clears
+// the optional ones, the password included (an empty password removes it as well)
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct RoomSettingsUpdate {
+    pub maxPlayers: Option<i32>,
+    #[serde(deserialize_with = "deserialize_present")]
+    pub defaultPackId: Option<Option<String>>,
+    #[serde(deserialize_with = "deserialize_present")]
+    pub questionDurationSec: Option<Option<i32>>,
+    #[serde(deserialize_with = "deserialize_present")]
+    pub scoringMode: Option<Option<ScoringCurve>>,
+    pub public: Option<bool>,
+    #[serde(deserialize_with = "deserialize_present")]
+    pub password: Option<Option<String>>,
+    pub inviteOnly: Option<bool>,
+    pub lateJoin: Option<LateJoinPolicy>,
+}
+
+impl RoomSettingsUpdate {
+    pub fn merge(self, current: &RoomSettings) -> RoomSettings {
+        RoomSettings {
+            maxPlayers: self.maxPlayers.unwrap_or(current.maxPlayers),
+            defaultPackId: self
+                .defaultPackId
+                .unwrap_or_else(|| current.defaultPackId.clone()),
+            questionDurationSec: self
+                .questionDurationSec
+                .unwrap_or(current.questionDurationSec),
+            scoringMode: self.scoringMode.unwrap_or(current.scoringMode),
+            public: self.public.unwrap_or(current.public),
+            // validate_room_settings takes an empty password as removing it
+            password: self.password.map(|password| password.unwrap_or_default()),
+            passwordHash: current.passwordHash.clone(),
+            hasPassword: current.hasPassword,
+            inviteOnly: self.inviteOnly.unwrap_or(current.inviteOnly),
+            lateJoin: self.lateJoin.unwrap_or(current.lateJoin),
+        }
+    }
+}
+
+// A field that is present, even as null, is Some so it can clear the current value
+fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum LateJoinPolicy {
+    never,
+    // Joining during a game is allowed, the new player answers from the next question on
+    nextQuestion,
 }
+
 impl HasId for Room {
     fn get_id(&self) -> String {
-        return self.id.clone();
+        self.id.clone()
     }
 }
 
+// Room summary served by the /rooms HTTP endpoint
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoomInfo {
+    pub id: String,
+    pub code: String,
+    pub currentPlayers: i32,
+    pub connectedPlayers: usize,
+    pub maxPlayers: i32,
+    pub gameRunning: bool,
+}
+
+// Public room entry returned by listRooms
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct PublicRoomInfo {
+    pub id: String,
+    pub code: String,
+    pub hostName: String,
+    pub currentPlayers: i32,
+    pub maxPlayers: i32,
+    pub packId: Option<String>,
+    pub packName: Option<String>,
+    pub inProgress: bool,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct RoomListFilter {
+    #[serde(default)]
+    pub packId: Option<String>,
+    // Leave out rooms without a free slot
+    #[serde(default)]
+    pub hideFull: bool,
+    // Only rooms with (true) or without (false) a running game
+    #[serde(default)]
+    pub inProgress: Option<bool>,
+}
+
 pub enum UserColors {
     Black,
     Yellow,
@@ -81,3 +230,80 @@ impl Distribution<UserColors> for Standard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::passwords::{hash_password, verify_password};
+
+    fn current() -> RoomSettings {
+        RoomSettings {
+            maxPlayers: 8,
+            defaultPackId: Some("pack".to_string()),
+            questionDurationSec: Some(20),
+            scoringMode: Some(ScoringCurve::linear),
+            public: true,
+            password: None,
+            passwordHash: Some(hash_password("secret")),
+            hasPassword: true,
+            inviteOnly: true,
+            lateJoin: LateJoinPolicy::nextQuestion,
+        }
+    }
+
+    fn update(json: &str) -> RoomSettings {
+        serde_json::from_str::<RoomSettingsUpdate>(json)
+            .unwrap()
+            .merge(&current())
+    }
+
+    #[test]
+    fn omitted_fields_keep_their_value() {
+        let settings = update(r#"{"maxPlayers": 4}"#);
+        assert_eq!(settings.maxPlayers, 4);
+        assert_eq!(settings.defaultPackId, Some("pack".to_string()));
+        assert_eq!(settings.questionDurationSec, Some(20));
+        assert!(matches!(settings.scoringMode, Some(ScoringCurve::linear)));
+        assert!(settings.public);
+        // Without a new password the hash is kept
+        assert_eq!(settings.password, None);
+        assert!(verify_password(
+            settings.passwordHash.as_ref().unwrap(),
+            "secret"
+        ));
+        assert!(settings.inviteOnly);
+        assert_eq!(settings.lateJoin, LateJoinPolicy::nextQuestion);
+    }
+
+    #[test]
+    fn null_clears_optional_fields() {
+        let settings =
+            update(r#"{"defaultPackId": null, "questionDurationSec": null, "scoringMode": null}"#);
+        assert_eq!(settings.defaultPackId, None);
+        assert_eq!(settings.questionDurationSec, None);
+        assert!(settings.scoringMode.is_none());
+        assert_eq!(settings.maxPlayers, 8);
+    }
+
+    #[test]
+    fn null_removes_the_password() {
+        assert_eq!(
+            update(r#"{"password": null}"#).password,
+            Some(String::new())
+        );
+        assert_eq!(update(r#"{"password": ""}"#).password, Some(String::new()));
+    }
+
+    #[test]
+    fn given_fields_replace_the_current_value() {
+        let settings = update(
+            r#"{"public": false, "inviteOnly": false, "lateJoin": "never",
+                "defaultPackId": "other", "password": "new"}"#,
+        );
+        assert!(!settings.public);
+        assert!(!settings.inviteOnly);
+        assert_eq!(settings.lateJoin, LateJoinPolicy::never);
+        assert_eq!(settings.defaultPackId, Some("other".to_string()));
+        assert_eq!(settings.password, Some("new".to_string()));
+    }
+}
@@ -0,0 +1,93 @@
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    models::game::{Pack, PackInfo},
+    validation::validate_pack,
+};
+
+// Reads and validates a pack, every validation problem is listed in the error
+pub fn load_pack(path: &Path) -> Result<Pack, String> {
+    let pack = read_pack(path)?;
+
+    let problems = validate_pack(&pack);
+    if !problems.is_empty() {
+        let problems: Vec<String> = problems.iter().map(|problem| problem.to_string()).collect();
+        return Err(format!("{}: {}", path.display(), problems.join("; ")));
+    }
+
+    Ok(pack)
+}
+
+pub fn read_pack(path: &Path) -> Result<Pack, String> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(error) => return Err(format!("{}: {}", path.display(), error)),
+    };
+    match serde_json::from_str::<Pack>(&data) {
+        Ok(pack) => Ok(pack),
+        Err(error) => Err(format!("{}: {}", path.display(), error)),
+    }
+}
+
+// Lists the *.json files in the directory, sorted by name
+pub fn find_pack_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => return Err(format!("{}: {}", dir.display(), error)),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("json")
+        })
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+// Loads every valid *.json pack in the directory, keyed by file name without extension
+pub fn scan_packs(dir: &Path) -> HashMap<String, Pack> {
+    let mut packs = HashMap::new();
+
+    let paths = match find_pack_files(dir) {
+        Ok(paths) => paths,
+        Err(error) => {
+            warn!("Cannot read packs directory {}", error);
+            return packs;
+        }
+    };
+
+    for path in paths {
+        let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(id) => id.to_string(),
+            None => continue,
+        };
+
+        match load_pack(&path) {
+            Ok(pack) => {
+                info!("Loaded pack {} ({} questions)", &id, pack.questions.len());
+                packs.insert(id, pack);
+            }
+            Err(error) => warn!("Skipping pack: {}", error),
+        }
+    }
+
+    packs
+}
+
+pub fn get_pack_info(id: &str, pack: &Pack) -> PackInfo {
+    PackInfo {
+        id: id.to_string(),
+        name: pack.name.clone(),
+        questionCount: pack.questions.len() as i32,
+        metadata: pack.metadata.clone(),
+    }
+}
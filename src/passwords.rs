@@ -0,0 +1,82 @@
+use ring::{
+    digest, pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::num::NonZeroU32;
+
+static ALGORITHM: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
+const HASH_LENGTH: usize = digest::SHA256_OUTPUT_LEN;
+const SALT_LENGTH: usize = 16;
+// Room passwords guard a game lobby, not an account, and every join attempt has to
+// derive the hash again, so this is kept low
+const ITERATIONS: u32 = 10_000;
+
+// Room passwords are only kept as a salted PBKDF2 hash
+#[derive(Clone)]
+pub struct PasswordHash {
+    salt: [u8; SALT_LENGTH],
+    hash: [u8; HASH_LENGTH],
+}
+
+impl std::fmt::Debug for PasswordHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PasswordHash")
+    }
+}
+
+fn iterations() -> NonZeroU32 {
+    NonZeroU32::new(ITERATIONS).unwrap()
+}
+
+pub fn hash_password(password: &str) -> PasswordHash {
+    let mut salt = [0u8; SALT_LENGTH];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("Failed to generate password salt");
+
+    let mut hash = [0u8; HASH_LENGTH];
+    pbkdf2::derive(
+        ALGORITHM,
+        iterations(),
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+
+    PasswordHash { salt, hash }
+}
+
+// Compares in constant time, so the answer time doesn't tell how much of the hash matched
+pub fn verify_password(password_hash: &PasswordHash, password: &str) -> bool {
+    pbkdf2::verify(
+        ALGORITHM,
+        iterations(),
+        &password_hash.salt,
+        password.as_bytes(),
+        &password_hash.hash,
+    )
+    .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_only_the_hashed_password() {
+        let password_hash = hash_password("secret");
+        assert!(verify_password(&password_hash, "secret"));
+        assert!(!verify_password(&password_hash, "Secret"));
+        assert!(!verify_password(&password_hash, "secret "));
+        assert!(!verify_password(&password_hash, ""));
+    }
+
+    #[test]
+    fn salts_every_hash() {
+        let first = hash_password("secret");
+        let second = hash_password("secret");
+        assert_ne!(first.salt, second.salt);
+        assert_ne!(first.hash, second.hash);
+        assert!(verify_password(&second, "secret"));
+    }
+}
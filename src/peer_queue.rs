@@ -0,0 +1,238 @@
+use log::warn;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+use tungstenite::Message;
+
+use crate::{
+    config::{OverflowPolicy, QueueConfig},
+    metrics::metrics,
+};
+
+// Bounded outgoing queue of a single connection. Droppable messages (timer ticks)
+// are the only ones that may be discarded, anything else overflowing closes the queue
+// and with it the connection
+struct PeerQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    capacity: usize,
+    overflow: OverflowPolicy,
+}
+
+struct QueueState {
+    messages: VecDeque<QueuedMessage>,
+    closed: bool,
+}
+
+struct QueuedMessage {
+    message: Message,
+    droppable: bool,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    Closed,
+    Overflow,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::Closed => write!(f, "Peer queue is closed"),
+            SendError::Overflow => write!(f, "Peer queue overflowed"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PeerSender {
+    queue: Arc<PeerQueue>,
+}
+
+pub struct PeerReceiver {
+    queue: Arc<PeerQueue>,
+}
+
+pub fn peer_channel(config: &QueueConfig) -> (PeerSender, PeerReceiver) {
+    let queue = Arc::new(PeerQueue {
+        state: Mutex::new(QueueState {
+            messages: VecDeque::new(),
+            closed: false,
+        }),
+        notify: Notify::new(),
+        capacity: config.capacity.max(1),
+        overflow: config.overflow.clone(),
+    });
+
+    (
+        PeerSender {
+            queue: queue.clone(),
+        },
+        PeerReceiver { queue },
+    )
+}
+
+impl PeerSender {
+    pub fn send(&self, message: Message) -> Result<(), SendError> {
+        self.push(message, false)
+    }
+
+    // For messages that are superseded by the next one of their kind, like timer ticks
+    pub fn send_droppable(&self, message: Message) -> Result<(), SendError> {
+        self.push(message, true)
+    }
+
+    fn push(&self, message: Message, droppable: bool) -> Result<(), SendError> {
+        let mut state = self.queue.state.lock().unwrap();
+        if state.closed {
+            return Err(SendError::Closed);
+        }
+
+        if state.messages.len() >= self.queue.capacity {
+            let oldest_droppable = match self.queue.overflow {
+                OverflowPolicy::DropOldestTicks => {
+                    state.messages.iter().position(|queued| queued.droppable)
+                }
+                OverflowPolicy::Disconnect => None,
+            };
+
+            match oldest_droppable {
+                Some(index) => {
+                    state.messages.remove(index);
+                    metrics().record_message_dropped();
+                }
+                None => {
+                    warn!(
+                        "Peer queue full ({} messages), closing connection",
+                        state.messages.len()
+                    );
+                    state.closed = true;
+                    state.messages.clear();
+                    drop(state);
+                    self.queue.notify.notify_one();
+                    return Err(SendError::Overflow);
+                }
+            }
+        }
+
+        state
+            .messages
+            .push_back(QueuedMessage { message, droppable });
+        drop(state);
+        self.queue.notify.notify_one();
+        Ok(())
+    }
+
+    pub fn depth(&self) -> usize {
+        self.queue.state.lock().unwrap().messages.len()
+    }
+}
+
+impl PeerReceiver {
+    // Returns None once the queue is closed, pending messages of a closed queue are discarded
+    pub async fn recv(&self) -> Option<Message> {
+        loop {
+            {
+                let mut state = self.queue.state.lock().unwrap();
+                if state.closed {
+                    return None;
+                }
+                if let Some(queued) = state.messages.pop_front() {
+                    return Some(queued.message);
+                }
+            }
+            self.queue.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for PeerReceiver {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.closed = true;
+        state.messages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(capacity: usize, overflow: OverflowPolicy) -> (PeerSender, PeerReceiver) {
+        peer_channel(&QueueConfig { capacity, overflow })
+    }
+
+    fn text(text: &str) -> Message {
+        Message::Text(text.to_string())
+    }
+
+    async fn received(rx: &PeerReceiver, count: usize) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for _ in 0..count {
+            messages.push(rx.recv().await.unwrap());
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn delivers_messages_in_order() {
+        let (tx, rx) = channel(4, OverflowPolicy::DropOldestTicks);
+        tx.send(text("a")).unwrap();
+        tx.send_droppable(text("b")).unwrap();
+        tx.send(text("c")).unwrap();
+        assert_eq!(tx.depth(), 3);
+        assert_eq!(
+            received(&rx, 3).await,
+            vec![text("a"), text("b"), text("c")]
+        );
+        assert_eq!(tx.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_the_oldest_tick() {
+        let (tx, rx) = channel(3, OverflowPolicy::DropOldestTicks);
+        tx.send(text("a")).unwrap();
+        tx.send_droppable(text("tick 1")).unwrap();
+        tx.send_droppable(text("tick 2")).unwrap();
+        tx.send(text("b")).unwrap();
+        assert_eq!(tx.depth(), 3);
+        assert_eq!(
+            received(&rx, 3).await,
+            vec![text("a"), text("tick 2"), text("b")]
+        );
+    }
+
+    #[tokio::test]
+    async fn full_queue_without_ticks_closes() {
+        let (tx, rx) = channel(2, OverflowPolicy::DropOldestTicks);
+        tx.send(text("a")).unwrap();
+        tx.send(text("b")).unwrap();
+        assert!(matches!(tx.send(text("c")), Err(SendError::Overflow)));
+        assert!(matches!(tx.send(text("d")), Err(SendError::Closed)));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn disconnect_policy_never_drops_ticks() {
+        let (tx, rx) = channel(2, OverflowPolicy::Disconnect);
+        tx.send_droppable(text("tick 1")).unwrap();
+        tx.send_droppable(text("tick 2")).unwrap();
+        assert!(matches!(
+            tx.send_droppable(text("tick 3")),
+            Err(SendError::Overflow)
+        ));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[test]
+    fn dropping_the_receiver_closes_the_queue() {
+        let (tx, rx) = channel(2, OverflowPolicy::DropOldestTicks);
+        tx.send(text("a")).unwrap();
+        drop(rx);
+        assert!(matches!(tx.send(text("b")), Err(SendError::Closed)));
+        assert_eq!(tx.depth(), 0);
+    }
+}
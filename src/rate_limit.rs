@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use crate::config::{CommandBudget, RateLimitConfig};
+
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(budget: &CommandBudget) -> Self {
+        TokenBucket {
+            capacity: budget.capacity,
+            refill_per_sec: budget.refill_per_sec,
+            tokens: budget.capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Takes one token, or returns how long until one is available
+    pub fn try_take(&mut self) -> Result<(), Duration> {
+        self.check()?;
+        self.tokens -= 1.0;
+        Ok(())
+    }
+
+    // Like try_take without taking the token
+    pub fn check(&mut self) -> Result<(), Duration> {
+        self.refill();
+        if self.tokens >= 1.0 {
+            return Ok(());
+        }
+
+        if self.refill_per_sec <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.refill_per_sec,
+        ))
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+pub enum RateLimitDecision {
+    Allowed,
+    Limited(Duration),
+    Disconnect,
+}
+
+// One per connection. Every command type has its own bucket, and rejected
+// commands drain a violation bucket which disconnects the peer when empty
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: HashMap<String, TokenBucket>,
+    violations: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        RateLimiter {
+            config: config.clone(),
+            buckets: HashMap::new(),
+            violations: TokenBucket::new(&CommandBudget {
+                capacity: config.max_violations as f64,
+                refill_per_sec: 1.0 / config.violation_decay_sec.max(1) as f64,
+            }),
+        }
+    }
+
+    pub fn check(&mut self, command_name: &str) -> RateLimitDecision {
+        let config = &self.config;
+        let bucket = self
+            .buckets
+            .entry(command_name.to_string())
+            .or_insert_with(|| {
+                TokenBucket::new(
+                    config
+                        .commands
+                        .get(command_name)
+                        .unwrap_or(&config.default_budget),
+                )
+            });
+
+        match bucket.try_take() {
+            Ok(_) => RateLimitDecision::Allowed,
+            Err(retry_after) => match self.violations.try_take() {
+                Ok(_) => RateLimitDecision::Limited(retry_after),
+                Err(_) => RateLimitDecision::Disconnect,
+            },
+        }
+    }
+}
+
+// Kept in AppState and shared by every connection, so reconnecting doesn't give an IP
+// new attempts. Each failure takes a token, an IP without tokens can't try again until
+// one refills
+pub struct FailedAttemptLimiter {
+    budget: CommandBudget,
+    buckets: HashMap<IpAddr, TokenBucket>,
+}
+
+impl FailedAttemptLimiter {
+    pub fn new(budget: &CommandBudget) -> Self {
+        FailedAttemptLimiter {
+            budget: budget.clone(),
+            buckets: HashMap::new(),
+        }
+    }
+
+    // Returns how long the IP has to wait if it used up its attempts
+    pub fn check(&mut self, ip: &IpAddr) -> Result<(), Duration> {
+        match self.buckets.get_mut(ip) {
+            Some(bucket) => bucket.check(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&mut self, ip: &IpAddr) {
+        // IPs that have not failed for a while are forgotten
+        self.buckets.retain(|_, bucket| !bucket.is_full());
+
+        let budget = &self.budget;
+        let bucket = self
+            .buckets
+            .entry(*ip)
+            .or_insert_with(|| TokenBucket::new(budget));
+        let _ = bucket.try_take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(capacity: f64, refill_per_sec: f64) -> CommandBudget {
+        CommandBudget {
+            capacity,
+            refill_per_sec,
+        }
+    }
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            default_budget: budget(2.0, 0.0),
+            commands: HashMap::from([("joinRoom".to_string(), budget(1.0, 0.0))]),
+            max_violations: 2,
+            violation_decay_sec: 3600,
+            ..RateLimitConfig::default()
+        }
+    }
+
+    #[test]
+    fn bucket_allows_up_to_its_capacity() {
+        let mut bucket = TokenBucket::new(&budget(3.0, 0.0));
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_ok());
+        assert_eq!(bucket.try_take(), Err(Duration::MAX));
+    }
+
+    #[test]
+    fn bucket_tells_how_long_until_the_next_token() {
+        let mut bucket = TokenBucket::new(&budget(1.0, 2.0));
+        assert!(bucket.try_take().is_ok());
+        let retry_after = bucket.try_take().unwrap_err();
+        assert!(retry_after > Duration::from_millis(400));
+        assert!(retry_after <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let mut bucket = TokenBucket::new(&budget(1.0, 1.0));
+        assert!(bucket.try_take().is_ok());
+        bucket.last_refill -= Duration::from_secs(5);
+        assert!(bucket.try_take().is_ok());
+        // Refilling never goes past the capacity
+        bucket.last_refill -= Duration::from_secs(5);
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_err());
+    }
+
+    #[test]
+    fn check_does_not_take_a_token() {
+        let mut bucket = TokenBucket::new(&budget(1.0, 0.0));
+        assert!(bucket.check().is_ok());
+        assert!(bucket.check().is_ok());
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.check().is_err());
+    }
+
+    #[test]
+    fn limiter_uses_per_command_budgets() {
+        let mut limiter = RateLimiter::new(&config());
+        assert!(matches!(
+            limiter.check("joinRoom"),
+            RateLimitDecision::Allowed
+        ));
+        assert!(matches!(
+            limiter.check("joinRoom"),
+            RateLimitDecision::Limited(_)
+        ));
+        // Other commands have their own bucket from the default budget
+        assert!(matches!(
+            limiter.check("listPacks"),
+            RateLimitDecision::Allowed
+        ));
+        assert!(matches!(
+            limiter.check("listPacks"),
+            RateLimitDecision::Allowed
+        ));
+    }
+
+    #[test]
+    fn limiter_disconnects_after_too_many_violations() {
+        let mut limiter = RateLimiter::new(&config());
+        assert!(matches!(
+            limiter.check("joinRoom"),
+            RateLimitDecision::Allowed
+        ));
+        assert!(matches!(
+            limiter.check("joinRoom"),
+            RateLimitDecision::Limited(_)
+        ));
+        assert!(matches!(
+            limiter.check("joinRoom"),
+            RateLimitDecision::Limited(_)
+        ));
+        assert!(matches!(
+            limiter.check("joinRoom"),
+            RateLimitDecision::Disconnect
+        ));
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[test]
+    fn failed_attempts_allow_unknown_ips() {
+        let mut limiter = FailedAttemptLimiter::new(&budget(2.0, 0.0));
+        assert!(limiter.check(&ip(1)).is_ok());
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn failed_attempts_block_after_the_budget() {
+        let mut limiter = FailedAttemptLimiter::new(&budget(2.0, 1.0));
+        limiter.record_failure(&ip(1));
+        assert!(limiter.check(&ip(1)).is_ok());
+        limiter.record_failure(&ip(1));
+        let retry_after = limiter.check(&ip(1)).unwrap_err();
+        assert!(retry_after > Duration::ZERO);
+        assert!(retry_after <= Duration::from_secs(1));
+        // Checking doesn't use up attempts or extend the wait
+        assert!(limiter.check(&ip(1)).is_err());
+    }
+
+    #[test]
+    fn failed_attempts_are_counted_per_ip() {
+        let mut limiter = FailedAttemptLimiter::new(&budget(1.0, 0.0));
+        limiter.record_failure(&ip(1));
+        assert!(limiter.check(&ip(1)).is_err());
+        assert!(limiter.check(&ip(2)).is_ok());
+    }
+
+    #[test]
+    fn failed_attempts_forget_refilled_ips() {
+        let mut limiter = FailedAttemptLimiter::new(&budget(2.0, 1.0));
+        limiter.record_failure(&ip(1));
+        limiter.buckets.get_mut(&ip(1)).unwrap().last_refill -= Duration::from_secs(5);
+        assert!(limiter.check(&ip(1)).is_ok());
+
+        limiter.record_failure(&ip(2));
+        assert!(!limiter.buckets.contains_key(&ip(1)));
+        assert!(limiter.buckets.contains_key(&ip(2)));
+    }
+}
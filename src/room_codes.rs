@@ -0,0 +1,101 @@
+use rand::{seq::SliceRandom, thread_rng};
+use std::sync::{Arc, Mutex};
+
+use crate::models::lobby::Room;
+
+type RoomList = Arc<Mutex<Vec<Room>>>;
+
+// No I, L or O, they are easily mistaken for 1 and 0 when read aloud or from a screen
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ";
+const CODE_LENGTH: usize = 5;
+
+fn generate_room_code() -> String {
+    let mut rng = thread_rng();
+    (0..CODE_LENGTH)
+        .map(|_| *CODE_ALPHABET.choose(&mut rng).unwrap() as char)
+        .collect()
+}
+
+// Gives the room a code no live room has and adds it to the list, both under the same lock.
+// Codes of removed rooms can be handed out again
+pub fn add_room(mut room: Room, room_list: RoomList) -> Room {
+    let mut rooms = room_list.lock().unwrap();
+    loop {
+        let code = generate_room_code();
+        if !rooms.iter().any(|room| room.code == code) {
+            room.code = code;
+            break;
+        }
+    }
+    rooms.push(room.clone());
+    room
+}
+
+// Looks a room up by its id or by its join code, codes are matched ignoring case
+pub fn find_room(id_or_code: &String, room_list: RoomList) -> Option<Room> {
+    let code = id_or_code.trim().to_uppercase();
+    return room_list
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|room| &room.id == id_or_code || room.code == code)
+        .cloned();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::lobby::RoomSettings;
+
+    fn room(id: &str) -> Room {
+        Room {
+            id: id.to_string(),
+            code: String::new(),
+            host_id: String::new(),
+            current_players: 0,
+            settings: RoomSettings::default(),
+            pack_id: None,
+        }
+    }
+
+    #[test]
+    fn codes_use_the_alphabet() {
+        let code = generate_room_code();
+        assert_eq!(code.len(), CODE_LENGTH);
+        assert!(code.bytes().all(|c| CODE_ALPHABET.contains(&c)));
+    }
+
+    #[test]
+    fn added_rooms_get_unique_codes() {
+        let rooms = RoomList::new(Mutex::new(Vec::new()));
+        let mut codes = Vec::new();
+        for index in 0..200 {
+            let added = add_room(room(&index.to_string()), rooms.clone());
+            codes.push(added.code);
+        }
+        assert_eq!(rooms.lock().unwrap().len(), 200);
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), 200);
+        assert!(rooms
+            .lock()
+            .unwrap()
+            .iter()
+            .zip(0..)
+            .all(|(room, index)| room.id == index.to_string()));
+    }
+
+    #[test]
+    fn finds_rooms_by_id_or_code() {
+        let rooms = RoomList::new(Mutex::new(Vec::new()));
+        let added = add_room(room("room-id"), rooms.clone());
+        add_room(room("other"), rooms.clone());
+
+        let by_id = find_room(&"room-id".to_string(), rooms.clone()).unwrap();
+        assert_eq!(by_id.code, added.code);
+        let by_code = find_room(&format!(" {} ", added.code.to_lowercase()), rooms.clone());
+        assert_eq!(by_code.unwrap().id, "room-id");
+        assert!(find_room(&"missing".to_string(), rooms.clone()).is_none());
+        assert!(find_room(&"ROOM-ID".to_string(), rooms).is_none());
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    models::{
+        communication::Response,
+        lobby::{PublicRoomInfo, RoomListFilter},
+    },
+    server_messages::send_message,
+    state::AppState,
+};
+
+type RoomListSubscriptions = Arc<Mutex<HashMap<String, RoomListSubscription>>>;
+
+const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Clone)]
+pub struct RoomListQuery {
+    pub filter: RoomListFilter,
+    pub page: usize,
+    pub page_size: usize,
+}
+
+pub struct RoomListSubscription {
+    query: RoomListQuery,
+    // Last page pushed, serialized, so unchanged pages are not sent again
+    last_sent: String,
+}
+
+// One page of public rooms matching the filter, ordered by room id so pages are stable
+pub fn list_public_rooms(query: &RoomListQuery, state: &AppState) -> Response {
+    let page_size = query.page_size.clamp(1, MAX_PAGE_SIZE);
+
+    let rooms: Vec<PublicRoomInfo> = {
+        let users = state.users.lock().unwrap();
+        let games = state.games.lock().unwrap();
+        let packs = state.packs.lock().unwrap();
+
+        let mut rooms: Vec<PublicRoomInfo> = state
+            .rooms
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|room| room.settings.public)
+            .map(|room| PublicRoomInfo {
+                id: room.id.clone(),
+                code: room.code.clone(),
+                hostName: users
+                    .iter()
+                    .find(|user| user.roomId == room.id && user.isHost)
+                    .map(|user| user.name.clone())
+                    .unwrap_or_default(),
+                currentPlayers: room.current_players,
+                maxPlayers: room.settings.maxPlayers,
+                packId: room.pack_id.clone(),
+                packName: room
+                    .pack_id
+                    .as_ref()
+                    .and_then(|pack_id| packs.get(pack_id))
+                    .map(|pack| pack.name.clone()),
+                inProgress: games.contains_key(&room.id),
+            })
+            .filter(|room| matches_filter(room, &query.filter))
+            .collect();
+        rooms.sort_by(|a, b| a.id.cmp(&b.id));
+        rooms
+    };
+
+    let total_rooms = rooms.len();
+    let rooms = rooms
+        .into_iter()
+        .skip(query.page.saturating_mul(page_size))
+        .take(page_size)
+        .collect();
+
+    Response::roomListResponse {
+        rooms,
+        page: query.page,
+        pageSize: page_size,
+        totalRooms: total_rooms,
+    }
+}
+
+fn matches_filter(room: &PublicRoomInfo, filter: &RoomListFilter) -> bool {
+    match &filter.packId {
+        Some(pack_id) if room.packId.as_ref() != Some(pack_id) => return false,
+        _ => (),
+    }
+    if filter.hideFull && room.currentPlayers >= room.maxPlayers {
+        return false;
+    }
+    match filter.inProgress {
+        Some(in_progress) if room.inProgress != in_progress => return false,
+        _ => (),
+    }
+    true
+}
+
+pub fn subscribe_room_list(
+    connection_id: &str,
+    query: RoomListQuery,
+    sent: &Response,
+    subscriptions: RoomListSubscriptions,
+) {
+    subscriptions.lock().unwrap().insert(
+        connection_id.to_string(),
+        RoomListSubscription {
+            query,
+            last_sent: serde_json::to_string(sent).unwrap(),
+        },
+    );
+}
+
+pub fn unsubscribe_room_list(connection_id: &String, subscriptions: RoomListSubscriptions) {
+    subscriptions.lock().unwrap().remove(connection_id);
+}
+
+// Pushes a subscriber's page again whenever its content changes. Woken by
+// AppState::notify_room_list_changed from the places that change rooms
+pub async fn watch_room_list(state: AppState) {
+    loop {
+        state.room_list_changed.notified().await;
+
+        let subscribers: Vec<(String, RoomListQuery)> = {
+            let subscriptions = state.room_list_subscriptions.lock().unwrap();
+            subscriptions
+                .iter()
+                .map(|(connection_id, subscription)| {
+                    (connection_id.clone(), subscription.query.clone())
+                })
+                .collect()
+        };
+
+        for (connection_id, query) in subscribers {
+            let response = list_public_rooms(&query, &state);
+            let serialized = serde_json::to_string(&response).unwrap();
+
+            let changed = match state
+                .room_list_subscriptions
+                .lock()
+                .unwrap()
+                .get_mut(&connection_id)
+            {
+                Some(subscription) if subscription.last_sent != serialized => {
+                    subscription.last_sent = serialized;
+                    true
+                }
+                _ => false,
+            };
+            if changed {
+                send_message(response, state.peers.clone(), &connection_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::lobby::{Room, RoomSettings};
+    use rusqlite::Connection;
+
+    fn room_info(pack_id: Option<&str>, current_players: i32, in_progress: bool) -> PublicRoomInfo {
+        PublicRoomInfo {
+            id: "room".to_string(),
+            code: "ABCDE".to_string(),
+            hostName: "host".to_string(),
+            currentPlayers: current_players,
+            maxPlayers: 4,
+            packId: pack_id.map(|pack_id| pack_id.to_string()),
+            packName: None,
+            inProgress: in_progress,
+        }
+    }
+
+    fn state_with_rooms(public: usize, private: usize) -> AppState {
+        let state = AppState::new(HashMap::new(), Connection::open_in_memory().unwrap());
+        for index in 0..public + private {
+            state.rooms.lock().unwrap().push(Room {
+                id: format!("room-{:02}", index),
+                code: format!("CODE{}", index),
+                host_id: String::new(),
+                current_players: 1,
+                settings: RoomSettings {
+                    public: index < public,
+                    ..RoomSettings::default()
+                },
+                pack_id: None,
+            });
+        }
+        state
+    }
+
+    fn query(page: usize, page_size: usize) -> RoomListQuery {
+        RoomListQuery {
+            filter: RoomListFilter::default(),
+            page,
+            page_size,
+        }
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = RoomListFilter::default();
+        assert!(matches_filter(&room_info(None, 4, true), &filter));
+        assert!(matches_filter(&room_info(Some("pack"), 0, false), &filter));
+    }
+
+    #[test]
+    fn filters_by_pack() {
+        let filter = RoomListFilter {
+            packId: Some("pack".to_string()),
+            ..RoomListFilter::default()
+        };
+        assert!(matches_filter(&room_info(Some("pack"), 1, false), &filter));
+        assert!(!matches_filter(
+            &room_info(Some("other"), 1, false),
+            &filter
+        ));
+        assert!(!matches_filter(&room_info(None, 1, false), &filter));
+    }
+
+    #[test]
+    fn hides_full_rooms() {
+        let filter = RoomListFilter {
+            hideFull: true,
+            ..RoomListFilter::default()
+        };
+        assert!(matches_filter(&room_info(None, 3, false), &filter));
+        assert!(!matches_filter(&room_info(None, 4, false), &filter));
+    }
+
+    #[test]
+    fn filters_by_game_running() {
+        let running = RoomListFilter {
+            inProgress: Some(true),
+            ..RoomListFilter::default()
+        };
+        let waiting = RoomListFilter {
+            inProgress: Some(false),
+            ..RoomListFilter::default()
+        };
+        assert!(matches_filter(&room_info(None, 1, true), &running));
+        assert!(!matches_filter(&room_info(None, 1, false), &running));
+        assert!(matches_filter(&room_info(None, 1, false), &waiting));
+        assert!(!matches_filter(&room_info(None, 1, true), &waiting));
+    }
+
+    #[test]
+    fn pages_public_rooms_in_id_order() {
+        let state = state_with_rooms(5, 3);
+
+        match list_public_rooms(&query(1, 2), &state) {
+            Response::roomListResponse {
+                rooms,
+                page,
+                pageSize,
+                totalRooms,
+            } => {
+                let ids: Vec<String> = rooms.into_iter().map(|room| room.id).collect();
+                assert_eq!(ids, vec!["room-02", "room-03"]);
+                assert_eq!(page, 1);
+                assert_eq!(pageSize, 2);
+                assert_eq!(totalRooms, 5);
+            }
+            _ => panic!("expected a room list"),
+        }
+    }
+
+    #[test]
+    fn clamps_page_size_and_returns_empty_pages_past_the_end() {
+        let state = state_with_rooms(3, 0);
+
+        match list_public_rooms(&query(0, 0), &state) {
+            Response::roomListResponse {
+                rooms, pageSize, ..
+            } => {
+                assert_eq!(pageSize, 1);
+                assert_eq!(rooms.len(), 1);
+            }
+            _ => panic!("expected a room list"),
+        }
+        match list_public_rooms(&query(usize::MAX, MAX_PAGE_SIZE + 1), &state) {
+            Response::roomListResponse {
+                rooms,
+                pageSize,
+                totalRooms,
+                ..
+            } => {
+                assert_eq!(pageSize, MAX_PAGE_SIZE);
+                assert!(rooms.is_empty());
+                assert_eq!(totalRooms, 3);
+            }
+            _ => panic!("expected a room list"),
+        }
+    }
+}
@@ -1,30 +1,34 @@
-use log::info;
+use log::{info, warn};
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
 };
 
-use futures_channel::mpsc::UnboundedSender;
 use tungstenite::protocol::Message;
 
-use crate::models::{communication::Response, lobby::User};
+use crate::{
+    metrics::metrics,
+    models::{communication::Response, lobby::User},
+    peer_queue::PeerSender,
+};
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<HashMap<String, Tx>>>;
+type PeerMap = Arc<Mutex<HashMap<String, PeerSender>>>;
 
 pub fn send_message(response: Response, peer_map: PeerMap, id: &String) {
+    send_reply(response, peer_map, id, &None);
+}
+
+// Direct reply to a command, carries the requestId the client sent with it
+pub fn send_reply(response: Response, peer_map: PeerMap, id: &String, request_id: &Option<String>) {
     info!("Sending msg to: {}", &id);
 
     let peers = peer_map.lock().unwrap();
     let broadcast_recipients = peers
         .iter()
         .filter(|(peer_addr, _)| peer_addr == &id)
-        .map(|(_, ws_sink)| ws_sink);
+        .collect::<Vec<_>>();
 
-    for recp in broadcast_recipients {
-        recp.unbounded_send(Message::Text(serde_json::to_string(&response).unwrap()))
-            .unwrap();
-    }
+    send_to_peers(&response, broadcast_recipients, request_id);
 
     info!("Message sent successfully to: {}", &id);
 }
@@ -32,12 +36,10 @@ pub fn send_message(response: Response, peer_map: PeerMap, id: &String) {
 pub fn broadcast_message_all(response: Response, peer_map: PeerMap) {
     info!("Sending broadcast to all connections");
     let peers = peer_map.lock().unwrap();
-    let broadcast_recipients = peers.iter().map(|(_, ws_sink)| ws_sink);
+    let broadcast_recipients = peers.iter().collect::<Vec<_>>();
 
-    for recp in broadcast_recipients {
-        recp.unbounded_send(Message::Text(serde_json::to_string(&response).unwrap()))
-            .unwrap();
-    }
+    metrics().record_broadcast();
+    send_to_peers(&response, broadcast_recipients, &None);
     info!("Broadcast sent successfully to all connections");
 }
 
@@ -47,19 +49,17 @@ pub fn broadcast_message_except(response: Response, peer_map: PeerMap, addr: &St
     let broadcast_recipients = peers
         .iter()
         .filter(|(peer_addr, _)| peer_addr != &addr)
-        .map(|(_, ws_sink)| ws_sink);
+        .collect::<Vec<_>>();
 
-    for recp in broadcast_recipients {
-        recp.unbounded_send(Message::Text(serde_json::to_string(&response).unwrap()))
-            .unwrap();
-    }
+    metrics().record_broadcast();
+    send_to_peers(&response, broadcast_recipients, &None);
     info!(
         "Broadcast sent successfully to all connections except: {}",
         &addr
     );
 }
 
-pub fn broadcast_message_room_all(response: Response, peer_map: PeerMap, user_list: &Vec<User>) {
+pub fn broadcast_message_room_all(response: Response, peer_map: PeerMap, user_list: &[User]) {
     info!("Sending broadcast to all room players");
 
     let peers = peer_map.lock().unwrap();
@@ -71,12 +71,10 @@ pub fn broadcast_message_room_all(response: Response, peer_map: PeerMap, user_li
                 .map(|user| &user.id)
                 .any(|id| &id == peer_addr)
         })
-        .map(|(_, ws_sink)| ws_sink);
+        .collect::<Vec<_>>();
 
-    for recp in broadcast_recipients {
-        recp.unbounded_send(Message::Text(serde_json::to_string(&response).unwrap()))
-            .unwrap();
-    }
+    metrics().record_broadcast();
+    send_to_peers(&response, broadcast_recipients, &None);
 
     info!("Broadcast sent successfully to all room players");
 }
@@ -84,7 +82,7 @@ pub fn broadcast_message_room_all(response: Response, peer_map: PeerMap, user_li
 pub fn broadcast_message_room_except(
     response: Response,
     peer_map: PeerMap,
-    user_list: &Vec<User>,
+    user_list: &[User],
     id: &String,
 ) {
     info!("Sending broadcast to all room players except: {}", &id);
@@ -98,15 +96,47 @@ pub fn broadcast_message_room_except(
                 .map(|user| &user.id)
                 .any(|user_id| &user_id == peer_addr)
         } && peer_addr != &id)
-        .map(|(_, ws_sink)| ws_sink);
+        .collect::<Vec<_>>();
 
-    for recp in broadcast_recipients {
-        recp.unbounded_send(Message::Text(serde_json::to_string(&response).unwrap()))
-            .unwrap();
-    }
+    metrics().record_broadcast();
+    send_to_peers(&response, broadcast_recipients, &None);
 
     info!(
         "Broadcast sent successfully to all room players except: {}",
         &id
     );
 }
+
+// A failed send means the peer is closing or fell too far behind, its connection
+// handler cleans it up, so the others still get the message
+fn send_to_peers(
+    response: &Response,
+    recipients: Vec<(&String, &PeerSender)>,
+    request_id: &Option<String>,
+) {
+    let message = match request_id {
+        Some(request_id) => {
+            let mut value = serde_json::to_value(response).unwrap();
+            value["requestId"] = request_id.clone().into();
+            Message::Text(value.to_string())
+        }
+        None => Message::Text(serde_json::to_string(response).unwrap()),
+    };
+    let droppable = matches!(response, Response::timerResponse { .. });
+    if let Response::errorResponse { errorCode, .. } = response {
+        metrics().record_error(*errorCode)
+    }
+
+    let mut sent = 0;
+    for (id, recp) in recipients {
+        let result = match droppable {
+            true => recp.send_droppable(message.clone()),
+            false => recp.send(message.clone()),
+        };
+        match result {
+            Ok(_) => sent += 1,
+            Err(error) => warn!("Could not send to {}: {}", id, error),
+        }
+    }
+    metrics().record_messages_sent(sent);
+}
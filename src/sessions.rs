@@ -0,0 +1,179 @@
+use chrono::{Days, Utc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+use crate::{
+    errors::ServerError,
+    jwtoken::{generate_token, Claims, TokenError},
+};
+
+type SessionList = Arc<Mutex<HashMap<String, Session>>>;
+
+// How long a rotated-out token keeps working, in case the client never got its replacement
+pub const ROTATED_SESSION_GRACE_SEC: i64 = 30;
+
+// Server-side half of a token, the token itself only carries the session and user ids
+#[derive(Clone)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub expires_at: i64,
+    // Session this one was rotated from, revoked once this one is first used
+    pub replaces: Option<String>,
+}
+
+// Starts a new session for the user and returns a token for it
+pub fn issue_session_token(user_id: &str, sessions: SessionList) -> Result<String, TokenError> {
+    start_session(user_id, None, sessions)
+}
+
+fn start_session(
+    user_id: &str,
+    replaces: Option<String>,
+    sessions: SessionList,
+) -> Result<String, TokenError> {
+    let session = Session {
+        id: Uuid::new_v4().to_string(),
+        user_id: user_id.to_string(),
+        expires_at: Utc::now()
+            .checked_add_days(Days::new(1))
+            .expect("Timestamp invalid")
+            .timestamp(),
+        replaces,
+    };
+    let token = generate_token(&session)?;
+
+    let mut sessions = sessions.lock().unwrap();
+    let now = Utc::now().timestamp();
+    sessions.retain(|_, session| session.expires_at > now);
+    sessions.insert(session.id.clone(), session);
+
+    Ok(token)
+}
+
+pub fn validate_session(claims: &Claims, sessions: SessionList) -> Result<Session, ServerError> {
+    let mut sessions = sessions.lock().unwrap();
+    let session = match sessions.get_mut(&claims.sid) {
+        Some(session) if session.user_id != claims.id => return Err(ServerError::SessionMismatch),
+        Some(session) if session.expires_at <= Utc::now().timestamp() => {
+            return Err(ServerError::SessionExpired)
+        }
+        Some(session) => session,
+        None => return Err(ServerError::SessionRevoked),
+    };
+
+    // The client has the new token, the one it replaced can go
+    let replaced = session.replaces.take();
+    let session = session.clone();
+    if let Some(replaced) = replaced {
+        sessions.remove(&replaced);
+    }
+    Ok(session)
+}
+
+// Replaces the session with a new one. Tokens for the old session keep working for
+// ROTATED_SESSION_GRACE_SEC, or until the new token is first used
+pub fn rotate_session(
+    session_id: &String,
+    user_id: &str,
+    sessions: SessionList,
+) -> Result<String, TokenError> {
+    let grace_end = Utc::now().timestamp() + ROTATED_SESSION_GRACE_SEC;
+    if let Some(session) = sessions.lock().unwrap().get_mut(session_id) {
+        session.expires_at = session.expires_at.min(grace_end);
+    }
+    start_session(user_id, Some(session_id.clone()), sessions)
+}
+
+pub fn revoke_user_sessions(user_id: &String, sessions: SessionList) {
+    sessions
+        .lock()
+        .unwrap()
+        .retain(|_, session| &session.user_id != user_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(id: &str, user_id: &str, expires_at: i64) -> Session {
+        Session {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            expires_at,
+            replaces: None,
+        }
+    }
+
+    fn claims(session_id: &str, user_id: &str) -> Claims {
+        Claims {
+            typ: "session".to_string(),
+            sid: session_id.to_string(),
+            id: user_id.to_string(),
+            exp: 0,
+        }
+    }
+
+    fn sessions_with(sessions: Vec<Session>) -> SessionList {
+        Arc::new(Mutex::new(
+            sessions
+                .into_iter()
+                .map(|session| (session.id.clone(), session))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn validates_live_sessions_only() {
+        let now = Utc::now().timestamp();
+        let sessions = sessions_with(vec![
+            session("live", "user", now + 60),
+            session("old", "user", now - 1),
+        ]);
+
+        assert!(validate_session(&claims("live", "user"), sessions.clone()).is_ok());
+        assert_eq!(
+            validate_session(&claims("live", "other"), sessions.clone()).err(),
+            Some(ServerError::SessionMismatch)
+        );
+        assert_eq!(
+            validate_session(&claims("old", "user"), sessions.clone()).err(),
+            Some(ServerError::SessionExpired)
+        );
+        assert_eq!(
+            validate_session(&claims("gone", "user"), sessions).err(),
+            Some(ServerError::SessionRevoked)
+        );
+    }
+
+    #[test]
+    fn rotated_session_lasts_until_the_new_one_is_used() {
+        crate::jwtoken::init_test_keys();
+        let now = Utc::now().timestamp();
+        let sessions = sessions_with(vec![session("old", "user", now + 3600)]);
+
+        rotate_session(&"old".to_string(), "user", sessions.clone()).unwrap();
+        let new_id = sessions
+            .lock()
+            .unwrap()
+            .values()
+            .find(|session| session.replaces.is_some())
+            .map(|session| session.id.clone())
+            .unwrap();
+
+        // The old token still works for the grace period
+        let old = validate_session(&claims("old", "user"), sessions.clone()).unwrap();
+        assert!(old.expires_at <= now + ROTATED_SESSION_GRACE_SEC + 1);
+
+        // First use of the new token revokes the old one
+        assert!(validate_session(&claims(&new_id, "user"), sessions.clone()).is_ok());
+        assert_eq!(
+            validate_session(&claims("old", "user"), sessions.clone()).err(),
+            Some(ServerError::SessionRevoked)
+        );
+        assert!(validate_session(&claims(&new_id, "user"), sessions).is_ok());
+    }
+}
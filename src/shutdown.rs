@@ -0,0 +1,213 @@
+use chrono::Utc;
+use futures_timer::Delay;
+use log::info;
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tungstenite::Message;
+
+use crate::{
+    config::ShutdownConfig,
+    helpers::send_game_command,
+    models::{communication::Response, game::GameCommand},
+    server_messages::broadcast_message_all,
+    state::AppState,
+};
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+pub async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+        _ = terminate.recv() => info!("Received SIGTERM"),
+    }
+}
+
+// No SIGTERM outside unix, Ctrl+C is the only shutdown signal
+#[cfg(not(unix))]
+pub async fn wait_for_signal() {
+    match tokio::signal::ctrl_c().await {
+        Ok(_) => info!("Received Ctrl+C"),
+        Err(error) => panic!("Failed to listen for Ctrl+C: {}", error),
+    }
+}
+
+// Called once the listeners are stopped. Running games get the grace period to finish,
+// the rest are ended so handle_game records them, then every peer gets a close frame.
+// Only finished game records survive a restart: rooms, sessions and the state of games
+// still running are held in memory and are lost
+pub async fn drain(state: &AppState, config: &ShutdownConfig) {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+
+    let grace_period = Duration::from_secs(config.grace_period_sec);
+    let reason = env::var("QUIZ_SHUTDOWN_REASON")
+        .ok()
+        .or_else(|| config.reason.clone());
+    let deadline = Utc::now() + chrono::Duration::seconds(config.grace_period_sec as i64);
+    broadcast_message_all(
+        Response::serverShutdown {
+            reason,
+            deadline: deadline.timestamp(),
+        },
+        state.peers.clone(),
+    );
+
+    info!(
+        "Waiting up to {}s for {} running games",
+        config.grace_period_sec,
+        state.games.lock().unwrap().len()
+    );
+    wait_until_empty(&state.games, grace_period).await;
+
+    let room_ids: Vec<String> = state.games.lock().unwrap().keys().cloned().collect();
+    if !room_ids.is_empty() {
+        info!("Ending {} games still running", room_ids.len());
+        for room_id in &room_ids {
+            send_game_command(&GameCommand::endGame {}, room_id, state.games.clone());
+        }
+        wait_until_empty(&state.games, Duration::from_secs(5)).await;
+    }
+
+    // Queued messages (game results) go out before the close frame
+    for peer in state.peers.lock().unwrap().values() {
+        let _ = peer.send(Message::Close(None));
+    }
+    wait_until_empty(&state.peers, Duration::from_secs(2)).await;
+}
+
+async fn wait_until_empty<T>(list: &Arc<Mutex<HashMap<String, T>>>, timeout: Duration) {
+    let started = Instant::now();
+    while !list.lock().unwrap().is_empty() && started.elapsed() < timeout {
+        Delay::new(Duration::from_millis(100)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::QueueConfig,
+        handlers::game_handler::handle_game,
+        models::{
+            game::{Answer, Pack, Question, QuestionKind},
+            lobby::User,
+        },
+        peer_queue::peer_channel,
+        storage::migrate,
+    };
+    use rusqlite::Connection;
+
+    fn pack() -> Pack {
+        Pack {
+            name: "pack".to_string(),
+            metadata: Default::default(),
+            questions: vec![Question {
+                text: "question".to_string(),
+                duration_sec: 30,
+                kind: QuestionKind::singleChoice {
+                    answers: vec![Answer {
+                        number: 0,
+                        text: "answer".to_string(),
+                    }],
+                    correct_answer: 0,
+                },
+            }],
+            scoring: Default::default(),
+            end_when_all_answered: false,
+        }
+    }
+
+    fn user() -> User {
+        User {
+            id: "user".to_string(),
+            name: "user".to_string(),
+            avatarPath: String::new(),
+            roomId: "room".to_string(),
+            isHost: true,
+            userColor: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn game_results_go_out_before_the_close_frame() {
+        let mut database = Connection::open_in_memory().unwrap();
+        migrate(&mut database).unwrap();
+        let state = AppState::new(HashMap::new(), database);
+
+        let (tx, rx) = peer_channel(&QueueConfig::default());
+        state.peers.lock().unwrap().insert("user".to_string(), tx);
+        state.users.lock().unwrap().push(user());
+
+        // Stands in for the connection handler, which leaves the peer list on close
+        let peers = state.peers.clone();
+        let connection = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while let Some(message) = rx.recv().await {
+                let closed = matches!(message, Message::Close(_));
+                received.push(message);
+                if closed {
+                    peers.lock().unwrap().remove("user");
+                    break;
+                }
+            }
+            received
+        });
+
+        let game = tokio::spawn(handle_game(
+            state.clone(),
+            vec![user()],
+            "room".to_string(),
+            "pack".to_string(),
+            pack(),
+        ));
+        while !state.games.lock().unwrap().contains_key("room") {
+            Delay::new(Duration::from_millis(10)).await;
+        }
+
+        // Slow database: the game is over well before its record is written
+        let database = state.database.clone();
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            let _database = database.lock().unwrap();
+            locked_tx.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(500));
+        });
+        locked_rx.recv().unwrap();
+
+        // No grace period, the game is ended straight away
+        drain(
+            &state,
+            &ShutdownConfig {
+                grace_period_sec: 0,
+                reason: None,
+            },
+        )
+        .await;
+        game.await.unwrap();
+        writer.join().unwrap();
+
+        let received = connection.await.unwrap();
+        let game_over = received.iter().position(|message| match message {
+            Message::Text(text) => text.contains("gameOverResponse"),
+            _ => false,
+        });
+        assert!(game_over.is_some());
+        assert!(matches!(received.last(), Some(Message::Close(_))));
+        assert!(game_over.unwrap() < received.len() - 1);
+    }
+}
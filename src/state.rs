@@ -0,0 +1,85 @@
+use futures_channel::mpsc::UnboundedSender;
+use rusqlite::Connection;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+use tungstenite::Message;
+
+use crate::{
+    config::get_config,
+    invites::Invite,
+    models::{
+        game::{GameState, Pack},
+        lobby::{Room, User},
+    },
+    peer_queue::PeerSender,
+    rate_limit::FailedAttemptLimiter,
+    room_list::RoomListSubscription,
+    sessions::Session,
+};
+
+pub type Tx = UnboundedSender<Message>;
+pub type TxTimeout = UnboundedSender<bool>;
+pub type PeerMap = Arc<Mutex<HashMap<String, PeerSender>>>;
+pub type UserList = Arc<Mutex<Vec<User>>>;
+pub type RoomList = Arc<Mutex<Vec<Room>>>;
+pub type GameList = Arc<Mutex<HashMap<String, Tx>>>;
+pub type UserTimeoutList = Arc<Mutex<HashMap<String, TxTimeout>>>;
+pub type GameStateList = Arc<Mutex<HashMap<String, GameState>>>;
+pub type PackList = Arc<Mutex<HashMap<String, Pack>>>;
+pub type Database = Arc<Mutex<Connection>>;
+pub type SessionList = Arc<Mutex<HashMap<String, Session>>>;
+pub type RoomListSubscriptions = Arc<Mutex<HashMap<String, RoomListSubscription>>>;
+pub type InviteList = Arc<Mutex<HashMap<String, Invite>>>;
+pub type FailedJoinLimiter = Arc<Mutex<FailedAttemptLimiter>>;
+
+// Everything the server shares between connections, cloning it only clones the handles
+#[derive(Clone)]
+pub struct AppState {
+    // Outgoing queue of every open connection, keyed by connection (user) id
+    pub peers: PeerMap,
+    pub users: UserList,
+    pub rooms: RoomList,
+    // Command channel of every running game, keyed by room id
+    pub games: GameList,
+    pub user_timeouts: UserTimeoutList,
+    pub game_states: GameStateList,
+    pub packs: PackList,
+    pub database: Database,
+    pub sessions: SessionList,
+    pub room_list_subscriptions: RoomListSubscriptions,
+    // Woken whenever something shown in the public room list changes
+    pub room_list_changed: Arc<Notify>,
+    pub invites: InviteList,
+    // Failed joinRoom attempts per IP
+    pub failed_joins: FailedJoinLimiter,
+}
+
+impl AppState {
+    pub fn new(packs: HashMap<String, Pack>, database: Connection) -> Self {
+        AppState {
+            peers: PeerMap::new(Mutex::new(HashMap::new())),
+            users: UserList::new(Mutex::new(Vec::new())),
+            rooms: RoomList::new(Mutex::new(Vec::new())),
+            games: GameList::new(Mutex::new(HashMap::new())),
+            user_timeouts: UserTimeoutList::new(Mutex::new(HashMap::new())),
+            game_states: GameStateList::new(Mutex::new(HashMap::new())),
+            packs: PackList::new(Mutex::new(packs)),
+            database: Database::new(Mutex::new(database)),
+            sessions: SessionList::new(Mutex::new(HashMap::new())),
+            room_list_subscriptions: RoomListSubscriptions::new(Mutex::new(HashMap::new())),
+            room_list_changed: Arc::new(Notify::new()),
+            invites: InviteList::new(Mutex::new(HashMap::new())),
+            failed_joins: FailedJoinLimiter::new(Mutex::new(FailedAttemptLimiter::new(
+                &get_config().rate_limit.failed_join_budget,
+            ))),
+        }
+    }
+
+    // Lets the room list watcher push fresh pages to its subscribers
+    pub fn notify_room_list_changed(&self) {
+        self.room_list_changed.notify_one();
+    }
+}
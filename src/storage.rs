@@ -0,0 +1,475 @@
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult, Row};
+use serde::Serialize;
+
+// Applied in order, the schema version is kept in the user_version pragma
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE games (
+        id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        pack_id TEXT NOT NULL,
+        pack_name TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        ended_at TEXT NOT NULL,
+        ended_early INTEGER NOT NULL
+    );
+    CREATE TABLE game_players (
+        game_id TEXT NOT NULL REFERENCES games(id) ON DELETE CASCADE,
+        user_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        score INTEGER NOT NULL,
+        correct_answers INTEGER NOT NULL,
+        rank INTEGER NOT NULL,
+        PRIMARY KEY (game_id, user_id)
+    );
+    CREATE TABLE game_answers (
+        game_id TEXT NOT NULL REFERENCES games(id) ON DELETE CASCADE,
+        question_index INTEGER NOT NULL,
+        user_id TEXT NOT NULL,
+        answer TEXT,
+        correct INTEGER NOT NULL,
+        points INTEGER NOT NULL,
+        response_ms INTEGER
+    );
+    CREATE INDEX game_answers_game ON game_answers(game_id);
+    CREATE INDEX games_started_at ON games(started_at);",
+    "CREATE INDEX game_players_user ON game_players(user_id);",
+];
+
+#[derive(Serialize, Clone)]
+pub struct GameRecord {
+    pub id: String,
+    pub room_id: String,
+    pub pack_id: String,
+    pub pack_name: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub ended_early: bool,
+    pub players: Vec<PlayerRecord>,
+    pub answers: Vec<AnswerRecord>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PlayerRecord {
+    pub user_id: String,
+    pub name: String,
+    pub score: i32,
+    pub correct_answers: i32,
+    pub rank: i32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AnswerRecord {
+    pub question_index: i32,
+    pub user_id: String,
+    // JSON of the answer payload, None if the player did not answer
+    pub answer: Option<String>,
+    pub correct: bool,
+    pub points: i32,
+    pub response_ms: Option<i64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct QuestionStats {
+    pub question_index: i32,
+    pub times_asked: i32,
+    pub correct_rate: f64,
+    pub average_response_ms: Option<f64>,
+}
+
+pub fn open_database(path: &str) -> SqlResult<Connection> {
+    let mut connection = Connection::open(path)?;
+    connection.pragma_update(None, "foreign_keys", true)?;
+    migrate(&mut connection)?;
+    Ok(connection)
+}
+
+pub fn migrate(connection: &mut Connection) -> SqlResult<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+pub fn record_game(connection: &mut Connection, game: &GameRecord) -> SqlResult<()> {
+    let transaction = connection.transaction()?;
+
+    transaction.execute(
+        "INSERT INTO games (id, room_id, pack_id, pack_name, started_at, ended_at, ended_early)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            game.id,
+            game.room_id,
+            game.pack_id,
+            game.pack_name,
+            game.started_at,
+            game.ended_at,
+            game.ended_early
+        ],
+    )?;
+    for player in &game.players {
+        transaction.execute(
+            "INSERT INTO game_players (game_id, user_id, name, score, correct_answers, rank)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                game.id,
+                player.user_id,
+                player.name,
+                player.score,
+                player.correct_answers,
+                player.rank
+            ],
+        )?;
+    }
+    for answer in &game.answers {
+        transaction.execute(
+            "INSERT INTO game_answers
+            (game_id, question_index, user_id, answer, correct, points, response_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                game.id,
+                answer.question_index,
+                answer.user_id,
+                answer.answer,
+                answer.correct,
+                answer.points,
+                answer.response_ms
+            ],
+        )?;
+    }
+
+    transaction.commit()
+}
+
+// Most recent games first, with players but without individual answers
+pub fn get_recent_games(connection: &Connection, limit: i64) -> SqlResult<Vec<GameRecord>> {
+    let mut statement = connection.prepare(
+        "SELECT id, room_id, pack_id, pack_name, started_at, ended_at, ended_early
+        FROM games ORDER BY started_at DESC LIMIT ?1",
+    )?;
+    let games = statement
+        .query_map(params![limit], game_from_row)?
+        .collect::<SqlResult<Vec<GameRecord>>>()?;
+
+    games
+        .into_iter()
+        .map(|mut game| {
+            game.players = get_game_players(connection, &game.id)?;
+            Ok(game)
+        })
+        .collect()
+}
+
+// A single game with its players and every answer given
+pub fn get_game(connection: &Connection, game_id: &str) -> SqlResult<Option<GameRecord>> {
+    let game = connection
+        .query_row(
+            "SELECT id, room_id, pack_id, pack_name, started_at, ended_at, ended_early
+            FROM games WHERE id = ?1",
+            params![game_id],
+            game_from_row,
+        )
+        .optional()?;
+
+    match game {
+        Some(mut game) => {
+            game.players = get_game_players(connection, game_id)?;
+            game.answers = get_game_answers(connection, game_id)?;
+            Ok(Some(game))
+        }
+        None => Ok(None),
+    }
+}
+
+// Games a player took part in, most recent first
+pub fn get_player_games(connection: &Connection, user_id: &str) -> SqlResult<Vec<GameRecord>> {
+    let mut statement = connection.prepare(
+        "SELECT games.id, games.room_id, games.pack_id, games.pack_name, games.started_at,
+            games.ended_at, games.ended_early
+        FROM games JOIN game_players ON game_players.game_id = games.id
+        WHERE game_players.user_id = ?1 ORDER BY games.started_at DESC",
+    )?;
+    let games = statement
+        .query_map(params![user_id], game_from_row)?
+        .collect::<SqlResult<Vec<GameRecord>>>()?;
+
+    games
+        .into_iter()
+        .map(|mut game| {
+            game.players = get_game_players(connection, &game.id)?;
+            Ok(game)
+        })
+        .collect()
+}
+
+// How each question of a pack has fared across all recorded games
+pub fn get_pack_question_stats(
+    connection: &Connection,
+    pack_id: &str,
+) -> SqlResult<Vec<QuestionStats>> {
+    let mut statement = connection.prepare(
+        "SELECT game_answers.question_index, COUNT(*), AVG(game_answers.correct),
+            AVG(game_answers.response_ms)
+        FROM game_answers JOIN games ON games.id = game_answers.game_id
+        WHERE games.pack_id = ?1
+        GROUP BY game_answers.question_index ORDER BY game_answers.question_index",
+    )?;
+    let stats = statement
+        .query_map(params![pack_id], |row| {
+            Ok(QuestionStats {
+                question_index: row.get(0)?,
+                times_asked: row.get(1)?,
+                correct_rate: row.get(2)?,
+                average_response_ms: row.get(3)?,
+            })
+        })?
+        .collect();
+    stats
+}
+
+fn get_game_players(connection: &Connection, game_id: &str) -> SqlResult<Vec<PlayerRecord>> {
+    let mut statement = connection.prepare(
+        "SELECT user_id, name, score, correct_answers, rank
+        FROM game_players WHERE game_id = ?1 ORDER BY rank, name",
+    )?;
+    let players = statement
+        .query_map(params![game_id], |row| {
+            Ok(PlayerRecord {
+                user_id: row.get(0)?,
+                name: row.get(1)?,
+                score: row.get(2)?,
+                correct_answers: row.get(3)?,
+                rank: row.get(4)?,
+            })
+        })?
+        .collect();
+    players
+}
+
+fn get_game_answers(connection: &Connection, game_id: &str) -> SqlResult<Vec<AnswerRecord>> {
+    let mut statement = connection.prepare(
+        "SELECT question_index, user_id, answer, correct, points, response_ms
+        FROM game_answers WHERE game_id = ?1 ORDER BY question_index, user_id",
+    )?;
+    let answers = statement
+        .query_map(params![game_id], |row| {
+            Ok(AnswerRecord {
+                question_index: row.get(0)?,
+                user_id: row.get(1)?,
+                answer: row.get(2)?,
+                correct: row.get(3)?,
+                points: row.get(4)?,
+                response_ms: row.get(5)?,
+            })
+        })?
+        .collect();
+    answers
+}
+
+fn game_from_row(row: &Row) -> SqlResult<GameRecord> {
+    Ok(GameRecord {
+        id: row.get(0)?,
+        room_id: row.get(1)?,
+        pack_id: row.get(2)?,
+        pack_name: row.get(3)?,
+        started_at: row.get(4)?,
+        ended_at: row.get(5)?,
+        ended_early: row.get(6)?,
+        players: Vec::new(),
+        answers: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> Connection {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection
+            .pragma_update(None, "foreign_keys", true)
+            .unwrap();
+        migrate(&mut connection).unwrap();
+        connection
+    }
+
+    fn game(
+        id: &str,
+        pack_id: &str,
+        started_at: &str,
+        players: &[(&str, &str, i32)],
+    ) -> GameRecord {
+        GameRecord {
+            id: id.to_string(),
+            room_id: "room".to_string(),
+            pack_id: pack_id.to_string(),
+            pack_name: "Pack".to_string(),
+            started_at: started_at.to_string(),
+            ended_at: started_at.to_string(),
+            ended_early: false,
+            players: players
+                .iter()
+                .enumerate()
+                .map(|(index, (user_id, name, score))| PlayerRecord {
+                    user_id: user_id.to_string(),
+                    name: name.to_string(),
+                    score: *score,
+                    correct_answers: 0,
+                    rank: index as i32 + 1,
+                })
+                .collect(),
+            answers: Vec::new(),
+        }
+    }
+
+    fn answer(question_index: i32, user_id: &str, correct: bool, response_ms: i64) -> AnswerRecord {
+        AnswerRecord {
+            question_index,
+            user_id: user_id.to_string(),
+            answer: Some("1".to_string()),
+            correct,
+            points: if correct { 100 } else { 0 },
+            response_ms: Some(response_ms),
+        }
+    }
+
+    fn ids(games: &[GameRecord]) -> Vec<&str> {
+        games.iter().map(|game| game.id.as_str()).collect()
+    }
+
+    #[test]
+    fn migrate_sets_the_schema_version_and_can_run_again() {
+        let mut connection = database();
+        let version: usize = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        migrate(&mut connection).unwrap();
+        let version: usize = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn records_and_reads_back_a_game() {
+        let mut connection = database();
+        let mut record = game(
+            "game",
+            "pack",
+            "2024-01-01T10:00:00Z",
+            &[("u1", "Ann", 200), ("u2", "Bob", 100)],
+        );
+        record.answers = vec![answer(0, "u2", false, 900), answer(0, "u1", true, 400)];
+        record_game(&mut connection, &record).unwrap();
+
+        let stored = get_game(&connection, "game").unwrap().unwrap();
+        assert_eq!(stored.pack_id, "pack");
+        let players: Vec<(String, i32)> = stored
+            .players
+            .iter()
+            .map(|player| (player.name.clone(), player.score))
+            .collect();
+        assert_eq!(
+            players,
+            vec![("Ann".to_string(), 200), ("Bob".to_string(), 100)]
+        );
+        let answers: Vec<(&str, bool)> = stored
+            .answers
+            .iter()
+            .map(|answer| (answer.user_id.as_str(), answer.correct))
+            .collect();
+        assert_eq!(answers, vec![("u1", true), ("u2", false)]);
+
+        assert!(get_game(&connection, "missing").unwrap().is_none());
+        assert!(record_game(&mut connection, &record).is_err());
+    }
+
+    #[test]
+    fn recent_games_are_newest_first_and_limited() {
+        let mut connection = database();
+        record_game(
+            &mut connection,
+            &game("old", "pack", "2024-01-01T10:00:00Z", &[]),
+        )
+        .unwrap();
+        record_game(
+            &mut connection,
+            &game("new", "pack", "2024-01-03T10:00:00Z", &[]),
+        )
+        .unwrap();
+        record_game(
+            &mut connection,
+            &game("mid", "pack", "2024-01-02T10:00:00Z", &[]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            ids(&get_recent_games(&connection, 10).unwrap()),
+            vec!["new", "mid", "old"]
+        );
+        assert_eq!(
+            ids(&get_recent_games(&connection, 2).unwrap()),
+            vec!["new", "mid"]
+        );
+    }
+
+    #[test]
+    fn player_games_match_the_user_id_not_the_name() {
+        let mut connection = database();
+        record_game(
+            &mut connection,
+            &game("first", "pack", "2024-01-01T10:00:00Z", &[("u1", "Ann", 0)]),
+        )
+        .unwrap();
+        record_game(
+            &mut connection,
+            &game(
+                "second",
+                "pack",
+                "2024-01-02T10:00:00Z",
+                &[("u1", "Ann", 0), ("u2", "Bob", 0)],
+            ),
+        )
+        .unwrap();
+        record_game(
+            &mut connection,
+            &game("other", "pack", "2024-01-03T10:00:00Z", &[("u3", "Ann", 0)]),
+        )
+        .unwrap();
+
+        let games = get_player_games(&connection, "u1").unwrap();
+        assert_eq!(ids(&games), vec!["second", "first"]);
+        assert_eq!(games[0].players.len(), 2);
+        assert!(get_player_games(&connection, "Ann").unwrap().is_empty());
+    }
+
+    #[test]
+    fn question_stats_cover_one_pack() {
+        let mut connection = database();
+        let mut first = game("first", "pack", "2024-01-01T10:00:00Z", &[]);
+        first.answers = vec![answer(0, "u1", true, 1000), answer(0, "u2", false, 3000)];
+        let mut second = game("second", "pack", "2024-01-02T10:00:00Z", &[]);
+        second.answers = vec![answer(0, "u1", true, 2000), answer(1, "u1", false, 500)];
+        let mut other = game("other", "other", "2024-01-03T10:00:00Z", &[]);
+        other.answers = vec![answer(0, "u1", false, 100)];
+        for record in [first, second, other] {
+            record_game(&mut connection, &record).unwrap();
+        }
+
+        let stats = get_pack_question_stats(&connection, "pack").unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].question_index, 0);
+        assert_eq!(stats[0].times_asked, 3);
+        assert!((stats[0].correct_rate - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats[0].average_response_ms, Some(2000.0));
+        assert_eq!(stats[1].times_asked, 1);
+        assert_eq!(stats[1].correct_rate, 0.0);
+    }
+}
@@ -0,0 +1,97 @@
+use futures_timer::Delay;
+use log::{info, warn};
+use native_tls::Identity;
+use std::{
+    env, fs,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio_native_tls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+
+type TlsAcceptorHandle = Arc<Mutex<TlsAcceptor>>;
+
+// Builds an acceptor from a PEM certificate and PKCS#8 key, or from a PKCS#12 bundle
+pub fn load_tls_acceptor(config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let identity = match (&config.cert_path, &config.key_path, &config.pkcs12_path) {
+        (Some(cert_path), Some(key_path), _) => {
+            let cert = read_file(cert_path)?;
+            let key = read_file(key_path)?;
+            match Identity::from_pkcs8(&cert, &key) {
+                Ok(identity) => identity,
+                Err(error) => return Err(format!("load_tls_acceptor: {}", error)),
+            }
+        }
+        (_, _, Some(pkcs12_path)) => {
+            let der = read_file(pkcs12_path)?;
+            let password = match &config.pkcs12_password_env {
+                Some(name) => env::var(name).unwrap_or_default(),
+                None => String::new(),
+            };
+            match Identity::from_pkcs12(&der, &password) {
+                Ok(identity) => identity,
+                Err(error) => return Err(format!("load_tls_acceptor: {}", error)),
+            }
+        }
+        _ => {
+            return Err("load_tls_acceptor: set cert_path and key_path, or pkcs12_path".to_string())
+        }
+    };
+
+    match native_tls::TlsAcceptor::builder(identity).build() {
+        Ok(acceptor) => Ok(TlsAcceptor::from(acceptor)),
+        Err(error) => Err(format!("load_tls_acceptor: {}", error)),
+    }
+}
+
+// Polls the certificate files and swaps in a new acceptor when they change.
+// Connections already established keep the certificate they were accepted with
+pub async fn watch_tls_certificates(config: TlsConfig, acceptor: TlsAcceptorHandle) {
+    let mut last_modified = get_modified_times(&config).await;
+
+    loop {
+        Delay::new(Duration::from_secs(config.reload_interval_sec)).await;
+
+        let modified = get_modified_times(&config).await;
+        if modified == last_modified {
+            continue;
+        }
+
+        // Reading the files and building the acceptor block as well
+        let load_config = config.clone();
+        let loaded =
+            match tokio::task::spawn_blocking(move || load_tls_acceptor(&load_config)).await {
+                Ok(loaded) => loaded,
+                Err(error) => Err(format!("watch_tls_certificates: {}", error)),
+            };
+        match loaded {
+            Ok(new_acceptor) => {
+                *acceptor.lock().unwrap() = new_acceptor;
+                last_modified = modified;
+                info!("Reloaded TLS certificate");
+            }
+            // Files may be mid-write, try again on the next poll
+            Err(error) => warn!("Could not reload TLS certificate: {}", error),
+        }
+    }
+}
+
+// Uses tokio's fs so a slow filesystem doesn't hold up a runtime worker
+async fn get_modified_times(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    let mut modified = Vec::new();
+    for path in [&config.cert_path, &config.key_path, &config.pkcs12_path] {
+        modified.push(match path {
+            Some(path) => tokio::fs::metadata(path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            None => None,
+        });
+    }
+    modified
+}
+
+fn read_file(path: &String) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|error| format!("{}: {}", path, error))
+}
@@ -0,0 +1,272 @@
+use std::{collections::HashSet, fmt};
+
+use crate::models::game::{Answer, Pack, Question, QuestionKind};
+
+pub struct PackProblem {
+    // None for problems with the pack as a whole
+    pub question_index: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for PackProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.question_index {
+            Some(index) => write!(f, "question {}: {}", index, self.message),
+            None => write!(f, "pack: {}", self.message),
+        }
+    }
+}
+
+// Returns every problem found in the pack, an empty list means the pack is playable
+pub fn validate_pack(pack: &Pack) -> Vec<PackProblem> {
+    let mut problems = Vec::new();
+    let mut pack_problem = |message: &str| {
+        problems.push(PackProblem {
+            question_index: None,
+            message: message.to_string(),
+        })
+    };
+
+    if pack.name.trim().is_empty() {
+        pack_problem("name is empty");
+    }
+    if pack.questions.is_empty() {
+        pack_problem("question list is empty");
+    }
+    if pack.scoring.min_points < 0 {
+        pack_problem("scoring min_points is negative");
+    }
+    if pack.scoring.min_points > pack.scoring.max_points {
+        pack_problem("scoring min_points is greater than max_points");
+    }
+
+    for (index, question) in pack.questions.iter().enumerate() {
+        for message in validate_question(question) {
+            problems.push(PackProblem {
+                question_index: Some(index),
+                message,
+            });
+        }
+    }
+
+    problems
+}
+
+fn validate_question(question: &Question) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if question.text.trim().is_empty() {
+        problems.push("text is empty".to_string());
+    }
+    if question.duration_sec <= 0 {
+        problems.push(format!(
+            "duration_sec must be positive, got {}",
+            question.duration_sec
+        ));
+    }
+
+    match &question.kind {
+        QuestionKind::singleChoice {
+            answers,
+            correct_answer,
+        } => {
+            problems.append(&mut validate_answers(answers));
+            if !answers
+                .iter()
+                .any(|answer| &answer.number == correct_answer)
+            {
+                problems.push(format!(
+                    "correct_answer {} is not among the answers",
+                    correct_answer
+                ));
+            }
+        }
+        QuestionKind::trueFalse { .. } => (),
+        QuestionKind::multiSelect {
+            answers,
+            correct_answers,
+        } => {
+            problems.append(&mut validate_answers(answers));
+            if correct_answers.is_empty() {
+                problems.push("correct_answers is empty".to_string());
+            }
+            let mut seen = HashSet::new();
+            for correct_answer in correct_answers {
+                if !seen.insert(correct_answer) {
+                    problems.push(format!("correct_answers lists {} twice", correct_answer));
+                }
+                if !answers
+                    .iter()
+                    .any(|answer| &answer.number == correct_answer)
+                {
+                    problems.push(format!(
+                        "correct_answers entry {} is not among the answers",
+                        correct_answer
+                    ));
+                }
+            }
+        }
+        QuestionKind::numeric { correct_value } => {
+            if !correct_value.is_finite() {
+                problems.push("correct_value is not a finite number".to_string());
+            }
+        }
+        QuestionKind::freeText { accepted_answers } => {
+            if accepted_answers.is_empty() {
+                problems.push("accepted_answers is empty".to_string());
+            }
+            if accepted_answers.iter().any(|text| text.trim().is_empty()) {
+                problems.push("accepted_answers contains an empty entry".to_string());
+            }
+        }
+    }
+
+    problems
+}
+
+fn validate_answers(answers: &[Answer]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if answers.is_empty() {
+        problems.push("answers list is empty".to_string());
+    }
+    let mut seen = HashSet::new();
+    for answer in answers {
+        if !seen.insert(answer.number) {
+            problems.push(format!("answer number {} is used twice", answer.number));
+        }
+        if answer.text.trim().is_empty() {
+            problems.push(format!("answer {} has empty text", answer.number));
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::game::Scoring;
+
+    fn pack(questions: Vec<Question>) -> Pack {
+        Pack {
+            name: "pack".to_string(),
+            metadata: Default::default(),
+            questions,
+            scoring: Scoring::default(),
+            end_when_all_answered: false,
+        }
+    }
+
+    fn question(kind: QuestionKind) -> Question {
+        Question {
+            text: "question".to_string(),
+            duration_sec: 10,
+            kind,
+        }
+    }
+
+    fn answers(numbers: &[i32]) -> Vec<Answer> {
+        numbers
+            .iter()
+            .map(|number| Answer {
+                number: *number,
+                text: format!("answer {}", number),
+            })
+            .collect()
+    }
+
+    fn messages(pack: &Pack) -> Vec<String> {
+        validate_pack(pack)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn accepts_a_playable_pack() {
+        let pack = pack(vec![
+            question(QuestionKind::singleChoice {
+                answers: answers(&[1, 2]),
+                correct_answer: 2,
+            }),
+            question(QuestionKind::trueFalse {
+                correct_answer: true,
+            }),
+            question(QuestionKind::multiSelect {
+                answers: answers(&[1, 2, 3]),
+                correct_answers: vec![1, 3],
+            }),
+            question(QuestionKind::numeric {
+                correct_value: 42.0,
+            }),
+            question(QuestionKind::freeText {
+                accepted_answers: vec!["Paris".to_string()],
+            }),
+        ]);
+        assert!(messages(&pack).is_empty());
+    }
+
+    #[test]
+    fn reports_pack_level_problems() {
+        let mut pack = pack(Vec::new());
+        pack.name = " ".to_string();
+        pack.scoring.min_points = 200;
+        assert_eq!(
+            messages(&pack),
+            vec![
+                "pack: name is empty",
+                "pack: question list is empty",
+                "pack: scoring min_points is greater than max_points",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_question_problems_with_their_index() {
+        let mut broken = question(QuestionKind::singleChoice {
+            answers: answers(&[1, 1]),
+            correct_answer: 3,
+        });
+        broken.duration_sec = 0;
+        let pack = pack(vec![
+            question(QuestionKind::trueFalse {
+                correct_answer: false,
+            }),
+            broken,
+        ]);
+        assert_eq!(
+            messages(&pack),
+            vec![
+                "question 1: duration_sec must be positive, got 0",
+                "question 1: answer number 1 is used twice",
+                "question 1: correct_answer 3 is not among the answers",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_multi_select_numeric_and_free_text_answers() {
+        let pack = pack(vec![
+            question(QuestionKind::multiSelect {
+                answers: answers(&[1, 2]),
+                correct_answers: vec![2, 2, 5],
+            }),
+            question(QuestionKind::numeric {
+                correct_value: f64::NAN,
+            }),
+            question(QuestionKind::freeText {
+                accepted_answers: vec![" ".to_string()],
+            }),
+        ]);
+        assert_eq!(
+            messages(&pack),
+            vec![
+                "question 0: correct_answers lists 2 twice",
+                "question 0: correct_answers entry 5 is not among the answers",
+                "question 1: correct_value is not a finite number",
+                "question 2: accepted_answers contains an empty entry",
+            ]
+        );
+    }
+}